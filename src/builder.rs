@@ -0,0 +1,203 @@
+use std::ops::Range;
+
+use crate::{
+    DisposalMethod, ExtensionData, Frame, FrameEncoder, GifEncoder, GifError, GifVersion, Repeat,
+};
+
+// Assembles a whole gif in memory, for when the frames don't come from a stream
+// configure it first, then push_frame every frame and finish with the trailer
+// the header goes out with the first frame: screen descriptor, global color table,
+// the looping extension and then the other extensions in the order they were added
+pub struct GifBuilder {
+    width: u16,
+    height: u16,
+    palette: Option<Vec<u8>>,
+    background: u8,
+    aspect_ratio: u8,
+    version: GifVersion,
+    repeat: Option<Repeat>,
+    interlaced: bool,
+    ping_pong: bool,
+    extensions: Vec<u8>,
+    encoder: FrameEncoder,
+    buf: Vec<u8>,
+    // where each frame starts and ends in buf, kept for ping_pong
+    frames: Vec<Range<usize>>,
+}
+
+impl GifBuilder {
+    // a GIF89a without a global color table, looping or extensions
+    pub fn new(width: u16, height: u16) -> Self {
+        Self {
+            width,
+            height,
+            palette: None,
+            background: 0,
+            aspect_ratio: 0,
+            version: GifVersion::Gif89a,
+            repeat: None,
+            interlaced: false,
+            ping_pong: false,
+            extensions: Vec::new(),
+            encoder: FrameEncoder::new(),
+            buf: Vec::new(),
+            frames: Vec::new(),
+        }
+    }
+
+    // global_palette is the global color table frames without a palette of their
+    // own are drawn with, by default there is none
+    // palettes over 256 colors panic
+    pub fn global_palette(self, palette: &[u8]) -> Self {
+        match self.try_global_palette(palette) {
+            Ok(b) => b,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    // same as global_palette, but returns an error instead of panicking
+    pub fn try_global_palette(mut self, palette: &[u8]) -> Result<Self, GifError> {
+        GifEncoder::try_global_palette_flags(palette)?;
+        self.palette = Some(palette.to_vec());
+        Ok(self)
+    }
+
+    // background is the global palette index the screen is cleared to, defaults to 0
+    pub fn background(mut self, index: u8) -> Self {
+        self.background = index;
+        self
+    }
+
+    // aspect_ratio is the raw pixel aspect ratio byte, see GifStream::aspect_ratio
+    pub fn aspect_ratio(mut self, aspect: u8) -> Self {
+        self.aspect_ratio = aspect;
+        self
+    }
+
+    // GIF87a has no extensions, so looping, comments and application extensions
+    // are left out along with frame delays and disposal
+    pub fn version(mut self, version: GifVersion) -> Self {
+        self.version = version;
+        self.encoder = FrameEncoder::with_version(version);
+        self
+    }
+
+    pub fn repeat(mut self, repeat: Repeat) -> Self {
+        self.repeat = Some(repeat);
+        self
+    }
+
+    pub fn interlaced(mut self, interlaced: bool) -> Self {
+        self.interlaced = interlaced;
+        self
+    }
+
+    // ping_pong plays the frames forward and then back, finish appends every frame
+    // but the first and last again in reverse, so 0 1 2 3 plays as 0 1 2 3 2 1
+    // the repeats are copies of the already encoded frames, delays and disposal included
+    pub fn ping_pong(mut self, ping_pong: bool) -> Self {
+        self.ping_pong = ping_pong;
+        self
+    }
+
+    pub fn comment(mut self, text: &str) -> Self {
+        GifEncoder::write_comment(&mut self.extensions, text);
+        self
+    }
+
+    pub fn application_extension(
+        mut self,
+        identifier: [u8; 8],
+        auth_code: [u8; 3],
+        data: &[u8],
+    ) -> Self {
+        let extension = ExtensionData::Application {
+            identifier,
+            auth_code,
+            data: data.to_vec(),
+        };
+        GifEncoder::write_extension(&mut self.extensions, extension);
+        self
+    }
+
+    // extensions that are already encoded, written after the others added so far
+    pub(crate) fn encoded_extensions(mut self, extensions: &[u8]) -> Self {
+        self.extensions.extend_from_slice(extensions);
+        self
+    }
+
+    // everything that comes before the first frame
+    pub fn header(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let flags = self
+            .palette
+            .as_deref()
+            .map(GifEncoder::global_palette_flags);
+        GifEncoder::write_screen_desc_with(
+            &mut buf,
+            self.width,
+            self.height,
+            flags,
+            self.background,
+            self.aspect_ratio,
+            self.version,
+        );
+
+        if let Some(palette) = &self.palette {
+            GifEncoder::write_color_table(&mut buf, palette);
+        }
+
+        if self.version == GifVersion::Gif89a {
+            if let Some(repeat) = self.repeat {
+                GifEncoder::write_loop_control(&mut buf, repeat);
+            }
+
+            buf.extend_from_slice(&self.extensions);
+        }
+
+        buf
+    }
+
+    // appends a frame, writing the header first if this is the first one
+    // frames that can't be encoded error and leave the gif as it was
+    pub fn push_frame(
+        &mut self,
+        frame: &Frame,
+        delay: u16,
+        dispose: DisposalMethod,
+    ) -> Result<(), GifError> {
+        let empty = self.buf.is_empty();
+        if empty {
+            self.buf = self.header();
+        }
+
+        let start = self.buf.len();
+        let written =
+            self.encoder
+                .try_write_frame(&mut self.buf, frame, delay, self.interlaced, dispose);
+
+        match written {
+            Ok(()) => self.frames.push(start..self.buf.len()),
+            Err(_) if empty => self.buf.clear(),
+            Err(_) => self.buf.truncate(start),
+        }
+
+        written
+    }
+
+    // the finished gif, ending with the trailer
+    pub fn finish(mut self) -> Vec<u8> {
+        if self.buf.is_empty() {
+            self.buf = self.header();
+        }
+
+        if self.ping_pong && self.frames.len() > 2 {
+            for frame in self.frames[1..self.frames.len() - 1].iter().rev() {
+                self.buf.extend_from_within(frame.clone());
+            }
+        }
+
+        GifEncoder::write_trailer(&mut self.buf);
+        self.buf
+    }
+}
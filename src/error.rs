@@ -0,0 +1,163 @@
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub enum GifError {
+    // quantization speed outside of 1..=30
+    InvalidSpeed(i32),
+    // palette color count outside of 1..=256
+    InvalidColorCount(usize),
+    // color count with a transparent index outside of 2..=256
+    InvalidTransparentColorCount(usize),
+    // width * height doesn't match the buffer length
+    InvalidDimensions { width: u16, height: u16, len: usize },
+    // width or height is 0, or there is no pixel data at all
+    EmptyFrame,
+    // pixel buffer length doesn't match width * height * channels
+    BufferSizeMismatch { expected: usize, got: usize },
+    // color table with more than 256 colors
+    PaletteTooLarge(usize),
+    // a pixel refers to a color past the end of the frame's color table
+    IndexOutOfRange { index: u8, colors: usize },
+    // frame delay floor below the 10ms (1/100s) a gif can represent
+    InvalidDelay(std::time::Duration),
+    // gamma that isn't a positive finite number
+    InvalidGamma,
+    // lzw minimum code size outside of 2..=8, or too small for the indices
+    InvalidCodeSize(u8),
+    // the lzw encoder rejected the image data
+    Lzw(weezl::LzwError),
+}
+
+impl fmt::Display for GifError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GifError::InvalidSpeed(speed) => {
+                write!(f, "speed must be between 1 and 30, got {}", speed)
+            }
+            GifError::InvalidColorCount(colors) => {
+                write!(f, "colors must be between 1 and 256, got {}", colors)
+            }
+            GifError::InvalidTransparentColorCount(colors) => write!(
+                f,
+                "colors must be between 2 and 256 with a transparent index, got {}",
+                colors
+            ),
+            GifError::InvalidDimensions { width, height, len } => write!(
+                f,
+                "invalid dimensions {}x{} for a buffer of {} pixels",
+                width, height, len
+            ),
+            GifError::EmptyFrame => write!(f, "frame has no pixels"),
+            GifError::BufferSizeMismatch { expected, got } => {
+                write!(f, "expected a buffer of {} bytes, got {}", expected, got)
+            }
+            GifError::PaletteTooLarge(colors) => {
+                write!(f, "palette has {} colors, at most 256 are allowed", colors)
+            }
+            GifError::IndexOutOfRange { index, colors } => write!(
+                f,
+                "color index {} is out of range for a palette of {} colors",
+                index, colors
+            ),
+            GifError::InvalidDelay(delay) => {
+                write!(f, "delays must be at least 10ms, got {:?}", delay)
+            }
+            GifError::InvalidGamma => write!(f, "gamma must be a positive finite number"),
+            GifError::InvalidCodeSize(size) => write!(
+                f,
+                "lzw code size {} is outside of 2 to 8 or too small for the indices",
+                size
+            ),
+            GifError::Lzw(e) => write!(f, "lzw encoding failed: {}", e),
+        }
+    }
+}
+
+// weezl's LzwError can't be compared, lzw errors are equal when they are the same kind
+impl PartialEq for GifError {
+    fn eq(&self, other: &Self) -> bool {
+        use GifError::*;
+
+        match (self, other) {
+            (InvalidSpeed(a), InvalidSpeed(b)) => a == b,
+            (InvalidColorCount(a), InvalidColorCount(b)) => a == b,
+            (InvalidTransparentColorCount(a), InvalidTransparentColorCount(b)) => a == b,
+            (
+                InvalidDimensions { width, height, len },
+                InvalidDimensions {
+                    width: w,
+                    height: h,
+                    len: l,
+                },
+            ) => (width, height, len) == (w, h, l),
+            (EmptyFrame, EmptyFrame) => true,
+            (
+                BufferSizeMismatch { expected, got },
+                BufferSizeMismatch {
+                    expected: e,
+                    got: g,
+                },
+            ) => (expected, got) == (e, g),
+            (PaletteTooLarge(a), PaletteTooLarge(b)) => a == b,
+            (
+                IndexOutOfRange { index, colors },
+                IndexOutOfRange {
+                    index: i,
+                    colors: c,
+                },
+            ) => (index, colors) == (i, c),
+            (InvalidDelay(a), InvalidDelay(b)) => a == b,
+            (InvalidGamma, InvalidGamma) => true,
+            (InvalidCodeSize(a), InvalidCodeSize(b)) => a == b,
+            (Lzw(a), Lzw(b)) => format!("{:?}", a) == format!("{:?}", b),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for GifError {}
+
+impl std::error::Error for GifError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GifError::Lzw(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+// Error produced by the gif streams
+// distinguishes a failing frame generator from a frame the encoder rejected
+// Io is only produced when a stream is written out, see GifStream::write_all_to
+#[derive(Debug)]
+pub enum StreamError<E> {
+    Generator(E),
+    Gif(GifError),
+    Io(std::io::Error),
+}
+
+impl<E> From<GifError> for StreamError<E> {
+    fn from(e: GifError) -> Self {
+        StreamError::Gif(e)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for StreamError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamError::Generator(e) => write!(f, "frame generator failed: {}", e),
+            StreamError::Gif(e) => write!(f, "{}", e),
+            StreamError::Io(e) => write!(f, "writing the gif failed: {}", e),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for StreamError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StreamError::Generator(_) => None,
+            StreamError::Gif(e) => Some(e),
+            StreamError::Io(e) => Some(e),
+        }
+    }
+}
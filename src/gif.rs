@@ -1,14 +1,71 @@
-use color_quant::NeuQuant;
-use weezl::{encode::Encoder as LzwEncoder, BitOrder};
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::quantize::{neuquant_rgb, Quantized, WEB_SAFE_TRANSPARENT};
+use crate::scale::resize_rgba;
+use crate::{GifBuilder, GifError, PaletteOptions, Quantize, Quantizer, ResizeFilter};
+use weezl::{decode::Decoder as LzwDecoder, encode::Encoder as LzwEncoder, BitOrder};
+
+#[cfg(feature = "image")]
+use std::num::TryFromIntError;
 
 pub struct GifEncoder;
 
+// every Vec based writer delegates to its io::Write based `_to` counterpart
+// the `_to` writers only fail when the writer does, which a Vec never does, so
+// those results are safe to discard. input a gif can't hold, like a color table
+// over 256 colors, is clamped by them and only rejected by the `try_` writers
 impl GifEncoder {
     pub fn write_screen_desc(buf: &mut Vec<u8>, width: u16, height: u16, flags: Option<u8>) {
-        buf.extend_from_slice(b"GIF89a");
-        buf.extend_from_slice(&width.to_le_bytes());
-        buf.extend_from_slice(&height.to_le_bytes());
-        buf.extend_from_slice(&[flags.unwrap_or(0), 0, 0]); // flags, bgcolor, aspect
+        let _ = Self::write_screen_desc_to(buf, width, height, flags);
+    }
+
+    pub fn write_screen_desc_to<W: Write>(
+        w: &mut W,
+        width: u16,
+        height: u16,
+        flags: Option<u8>,
+    ) -> io::Result<()> {
+        Self::write_screen_desc_with_to(w, width, height, flags, 0, 0, GifVersion::Gif89a)
+    }
+
+    // same as write_screen_desc, with the background color index
+    // (the global palette index shown by DisposalMethod::Background),
+    // the pixel aspect ratio, where n means (n + 15) / 64 and 0 means no information
+    // and the signature version
+    pub fn write_screen_desc_with(
+        buf: &mut Vec<u8>,
+        width: u16,
+        height: u16,
+        flags: Option<u8>,
+        background: u8,
+        aspect: u8,
+        version: GifVersion,
+    ) {
+        let _ =
+            Self::write_screen_desc_with_to(buf, width, height, flags, background, aspect, version);
+    }
+
+    pub fn write_screen_desc_with_to<W: Write>(
+        w: &mut W,
+        width: u16,
+        height: u16,
+        flags: Option<u8>,
+        background: u8,
+        aspect: u8,
+        version: GifVersion,
+    ) -> io::Result<()> {
+        w.write_all(version.signature())?;
+        w.write_all(&width.to_le_bytes())?;
+        w.write_all(&height.to_le_bytes())?;
+        w.write_all(&[flags.unwrap_or(0), background, aspect]) // flags, bgcolor, aspect
+    }
+
+    // same as global_palette_flags, but errors on palettes over 256 colors
+    // instead of claiming a 256 color table for them
+    pub fn try_global_palette_flags(palette: &[u8]) -> Result<u8, GifError> {
+        check_palette_len(palette)?;
+        Ok(Self::global_palette_flags(palette))
     }
 
     pub fn global_palette_flags(palette: &[u8]) -> u8 {
@@ -23,35 +80,91 @@ impl GifEncoder {
     }
 
     pub fn write_color_table(buf: &mut Vec<u8>, table: &[u8]) {
-        let num_colors = table.len() / 3;
+        let _ = Self::write_color_table_to(buf, table);
+    }
+
+    // same as write_color_table, but errors on tables over 256 colors
+    // instead of writing a malformed color table
+    pub fn try_write_color_table(buf: &mut Vec<u8>, table: &[u8]) -> Result<(), GifError> {
+        check_palette_len(table)?;
+        Self::write_color_table(buf, table);
+        Ok(())
+    }
+
+    // tables over 256 colors are cut to their first 256, which is all a frame's
+    // u8 indices can reach, use try_write_color_table to reject them instead
+    pub fn write_color_table_to<W: Write>(w: &mut W, table: &[u8]) -> io::Result<()> {
+        Self::write_color_table_padded_to(w, table, [0, 0, 0])
+    }
+
+    // same as write_color_table, but fills the unused entries up to the next
+    // power of two with pad instead of black, for when black means something
+    // or a transparent or background index could land in the padding
+    pub fn write_color_table_padded(buf: &mut Vec<u8>, table: &[u8], pad: [u8; 3]) {
+        let _ = Self::write_color_table_padded_to(buf, table, pad);
+    }
+
+    pub fn write_color_table_padded_to<W: Write>(
+        w: &mut W,
+        table: &[u8],
+        pad: [u8; 3],
+    ) -> io::Result<()> {
+        let num_colors = (table.len() / 3).min(256);
 
-        buf.extend_from_slice(&table[..num_colors * 3]);
+        w.write_all(&table[..num_colors * 3])?;
 
         let size = flag_size(num_colors);
 
-        // Pad with black
         for _ in 0..(2usize << size).saturating_sub(num_colors) {
-            buf.extend_from_slice(&[0, 0, 0]);
+            w.write_all(&pad)?;
         }
+
+        Ok(())
     }
 
+    #[deprecated(note = "use write_loop_control with Repeat::Finite")]
     pub fn write_repeat(buf: &mut Vec<u8>, repeat: u16) {
-        Self::write_extension(buf, ExtensionData::Repetitions(repeat))
+        Self::write_loop_control(buf, Repeat::Finite(repeat))
     }
 
+    #[deprecated(note = "use write_loop_control with Repeat::Infinite")]
     pub fn write_loop(buf: &mut Vec<u8>) {
-        Self::write_extension(buf, ExtensionData::InfiniteRepetitions)
+        Self::write_loop_control(buf, Repeat::Infinite)
+    }
+
+    // writes the NETSCAPE2.0 looping extension for repeat, see Repeat
+    pub fn write_loop_control(buf: &mut Vec<u8>, repeat: Repeat) {
+        let _ = Self::write_loop_control_to(buf, repeat);
+    }
+
+    pub fn write_loop_control_to<W: Write>(w: &mut W, repeat: Repeat) -> io::Result<()> {
+        match repeat {
+            // a loop count of 0 is what the file format uses for forever
+            Repeat::Infinite => Self::write_extension_to(w, netscape_loop(0)),
+            Repeat::Finite(0) => Ok(()),
+            Repeat::Finite(n) => Self::write_extension_to(w, netscape_loop(n)),
+        }
+    }
+
+    pub fn write_comment(buf: &mut Vec<u8>, text: &str) {
+        Self::write_extension(buf, ExtensionData::Comment(text.to_string()))
     }
 
     pub fn write_extension(buf: &mut Vec<u8>, extension: ExtensionData) {
+        let _ = Self::write_extension_to(buf, extension);
+    }
+
+    #[allow(deprecated)]
+    pub fn write_extension_to<W: Write>(w: &mut W, extension: ExtensionData) -> io::Result<()> {
         use ExtensionData::*;
 
-        // Don't write empty extensions
-        if let Repetitions(0) = extension {
-            return;
-        }
+        let extension = match extension {
+            Repetitions(repeat) => return Self::write_loop_control_to(w, Repeat::Finite(repeat)),
+            InfiniteRepetitions => return Self::write_loop_control_to(w, Repeat::Infinite),
+            extension => extension,
+        };
 
-        buf.push(0x21);
+        w.write_all(&[0x21])?;
 
         match extension {
             Control {
@@ -59,32 +172,28 @@ impl GifEncoder {
                 delay,
                 transparency_idx: trns,
             } => {
-                buf.push(0xF9);
-                buf.push(4);
-                buf.push(flags);
-                buf.extend_from_slice(&delay.to_le_bytes());
-                buf.push(trns);
+                w.write_all(&[0xF9, 4, flags])?;
+                w.write_all(&delay.to_le_bytes())?;
+                w.write_all(&[trns])?;
             }
-            InfiniteRepetitions => {
-                buf.push(0xFF);
-                buf.push(11);
-                buf.extend_from_slice(b"NETSCAPE2.0");
-                buf.push(3);
-                buf.push(1);
-                buf.extend_from_slice(&0u16.to_le_bytes())
+            Application {
+                identifier,
+                auth_code,
+                data,
+            } => {
+                w.write_all(&[0xFF, 11])?;
+                w.write_all(&identifier)?;
+                w.write_all(&auth_code)?;
+                write_sub_blocks_to(w, &data)?;
             }
-            Repetitions(repeat) => {
-                buf.push(0xFF);
-                buf.push(11);
-                buf.extend_from_slice(b"NETSCAPE2.0");
-                buf.push(3);
-                buf.push(1);
-
-                buf.extend_from_slice(&repeat.to_le_bytes())
+            Repetitions(_) | InfiniteRepetitions => unreachable!(),
+            Comment(text) => {
+                w.write_all(&[0xFE])?;
+                write_sub_blocks_to(w, text.as_bytes())?;
             }
         }
 
-        buf.push(0);
+        w.write_all(&[0])
     }
 
     pub fn write_frame_header(
@@ -94,21 +203,69 @@ impl GifEncoder {
         interlaced: bool,
         dispose: DisposalMethod,
     ) {
-        let t = frame.transparent.unwrap_or(0);
-        Self::write_extension(
-            buf,
+        let _ = Self::write_frame_header_to(buf, frame, delay, interlaced, dispose);
+    }
+
+    // same as write_frame_header, but rejects frames whose geometry doesn't
+    // match their buffer instead of writing a malformed image descriptor
+    pub fn try_write_frame_header(
+        buf: &mut Vec<u8>,
+        frame: &Frame,
+        delay: u16,
+        interlaced: bool,
+        dispose: DisposalMethod,
+    ) -> Result<(), GifError> {
+        frame.validate()?;
+        Self::write_frame_header(buf, frame, delay, interlaced, dispose);
+        Ok(())
+    }
+
+    pub fn write_frame_header_to<W: Write>(
+        w: &mut W,
+        frame: &Frame,
+        delay: u16,
+        interlaced: bool,
+        dispose: DisposalMethod,
+    ) -> io::Result<()> {
+        // a control extension with nothing in it says nothing, leave it out
+        if delay == 0 && frame.transparent.is_none() && dispose == DisposalMethod::Any {
+            return Self::write_image_desc_to(w, frame, interlaced);
+        }
+
+        // disposal method lives in bits 2-4, the transparent color flag in bit 0
+        let mut flags = (dispose as u8) << 2;
+        if frame.transparent.is_some() {
+            flags |= 1;
+        }
+
+        Self::write_extension_to(
+            w,
             ExtensionData::Control {
-                flags: dispose as u8 | 1 << 3,
+                flags,
                 delay,
-                transparency_idx: t,
+                transparency_idx: frame.transparent.unwrap_or(0),
             },
-        );
+        )?;
+
+        Self::write_image_desc_to(w, frame, interlaced)
+    }
 
-        buf.push(0x2C);
-        buf.extend_from_slice(&0u16.to_le_bytes()); // top
-        buf.extend_from_slice(&0u16.to_le_bytes()); // left
-        buf.extend_from_slice(&frame.width.to_le_bytes());
-        buf.extend_from_slice(&frame.height.to_le_bytes());
+    // the image descriptor and local color table of a frame, without the
+    // graphic control extension in front of it (all a GIF87a frame header has)
+    pub fn write_image_desc(buf: &mut Vec<u8>, frame: &Frame, interlaced: bool) {
+        let _ = Self::write_image_desc_to(buf, frame, interlaced);
+    }
+
+    pub fn write_image_desc_to<W: Write>(
+        w: &mut W,
+        frame: &Frame,
+        interlaced: bool,
+    ) -> io::Result<()> {
+        w.write_all(&[0x2C])?;
+        w.write_all(&frame.left.to_le_bytes())?;
+        w.write_all(&frame.top.to_le_bytes())?;
+        w.write_all(&frame.width.to_le_bytes())?;
+        w.write_all(&frame.height.to_le_bytes())?;
 
         let mut flags = 0;
         if interlaced {
@@ -118,10 +275,10 @@ impl GifEncoder {
         if let Some(palette) = &frame.palette {
             flags |= 1 << 7; // local color table
             flags |= flag_size(palette.len() / 3);
-            buf.push(flags);
-            Self::write_color_table(buf, &palette);
+            w.write_all(&[flags])?;
+            Self::write_color_table_to(w, palette)
         } else {
-            buf.push(flags);
+            w.write_all(&[flags])
         }
     }
 
@@ -132,41 +289,187 @@ impl GifEncoder {
         interlaced: bool,
         dispose: DisposalMethod,
     ) {
-        Self::write_frame_header(buf, frame, delay, interlaced, dispose);
-        Self::write_image_block(buf, &frame.buffer);
+        let _ = Self::write_frame_to(buf, frame, delay, interlaced, dispose);
+    }
+
+    pub fn write_frame_to<W: Write>(
+        w: &mut W,
+        frame: &Frame,
+        delay: u16,
+        interlaced: bool,
+        dispose: DisposalMethod,
+    ) -> io::Result<()> {
+        Self::write_frame_header_to(w, frame, delay, interlaced, dispose)?;
+
+        if interlaced {
+            let rows = interlace(&frame.buffer, frame.width, frame.height);
+            Self::write_image_block_to(w, &rows)
+        } else {
+            Self::write_image_block_to(w, &frame.buffer)
+        }
+    }
+
+    // same as write_frame, but rejects invalid frames and surfaces
+    // lzw encoding errors instead of writing a broken image block
+    pub fn try_write_frame(
+        buf: &mut Vec<u8>,
+        frame: &Frame,
+        delay: u16,
+        interlaced: bool,
+        dispose: DisposalMethod,
+    ) -> Result<(), GifError> {
+        FrameEncoder::new().try_write_frame(buf, frame, delay, interlaced, dispose)
     }
 
     pub fn write_image_block(buf: &mut Vec<u8>, data: &[u8]) {
+        let _ = Self::write_image_block_to(buf, data);
+    }
+
+    pub fn write_image_block_to<W: Write>(w: &mut W, data: &[u8]) -> io::Result<()> {
+        Self::write_image_block_with_to(w, data, &mut Vec::new())
+    }
+
+    // same as write_image_block, but lzw encodes into scratch instead of a fresh Vec
+    // so writing many frames can keep reusing one allocation
+    pub fn write_image_block_with(buf: &mut Vec<u8>, data: &[u8], scratch: &mut Vec<u8>) {
+        let _ = Self::write_image_block_with_to(buf, data, scratch);
+    }
+
+    pub fn write_image_block_with_to<W: Write>(
+        w: &mut W,
+        data: &[u8],
+        scratch: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        scratch.clear();
+        lzw_encode(scratch, data);
+        Self::write_encoded_image_block_to(w, scratch)
+    }
+
+    // same as write_image_block, but with a fixed lzw minimum code size, e.g.
+    // min_code_size_for_palette of a global palette so every frame uses the same one
+    // errors like try_lzw_encode_with_code_size
+    pub fn try_write_image_block_with_code_size(
+        buf: &mut Vec<u8>,
+        data: &[u8],
+        min_code_size: u8,
+    ) -> Result<(), GifError> {
         let mut lzw = Vec::new();
-        lzw_encode(&mut lzw, data);
+        try_lzw_encode_with_code_size(&mut lzw, data, min_code_size)?;
         Self::write_encoded_image_block(buf, &lzw);
+        Ok(())
     }
 
     pub fn write_encoded_image_block(buf: &mut Vec<u8>, data: &[u8]) {
+        let _ = Self::write_encoded_image_block_to(buf, data);
+    }
+
+    // writes the lzw data straight to the sink in 255 byte sub-blocks
+    pub fn write_encoded_image_block_to<W: Write>(w: &mut W, data: &[u8]) -> io::Result<()> {
         let (&min_code_size, data) = data.split_first().unwrap_or((&2, &[]));
-        buf.push(min_code_size);
+        w.write_all(&[min_code_size])?;
+        write_sub_blocks_to(w, data)?;
+        w.write_all(&[0])
+    }
+
+    pub fn write_trailer(buf: &mut Vec<u8>) {
+        let _ = Self::write_trailer_to(buf);
+    }
+
+    pub fn write_trailer_to<W: Write>(w: &mut W) -> io::Result<()> {
+        w.write_all(&[0x3B])
+    }
 
-        let mut iter = data.chunks_exact(0xFF);
-        while let Some(chunk) = iter.next() {
-            buf.push(0xFF);
-            buf.extend_from_slice(chunk);
+    // encodes a complete gif from frames already in memory
+    // screen descriptor, the looping extension if any, every frame and the trailer
+    // frames without a palette of their own have no color table to refer to
+    pub fn encode_gif(
+        width: u16,
+        height: u16,
+        frames: &[Frame],
+        delay: u16,
+        repeat: Option<Repeat>,
+        dispose: DisposalMethod,
+    ) -> Result<Vec<u8>, GifError> {
+        let mut builder = GifBuilder::new(width, height).global_palette(&[]);
+        if let Some(repeat) = repeat {
+            builder = builder.repeat(repeat);
         }
 
-        let rem = iter.remainder();
-        if !rem.is_empty() {
-            buf.push(rem.len() as u8);
-            buf.extend_from_slice(rem);
+        for frame in frames {
+            builder.push_frame(frame, delay, dispose)?;
         }
 
-        buf.push(0);
+        Ok(builder.finish())
     }
+}
 
-    pub fn write_trailer(buf: &mut Vec<u8>) {
-        buf.push(0x3B);
+// encodes frames like GifEncoder::try_write_frame, but keeps its interlace
+// and lzw scratch buffers around so consecutive frames don't reallocate them
+#[derive(Default)]
+pub struct FrameEncoder {
+    rows: Vec<u8>,
+    lzw: Vec<u8>,
+    version: GifVersion,
+    min_code_size: Option<u8>,
+}
+
+impl FrameEncoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // GIF87a has no graphic control extension, so frames are written
+    // without delay, disposal or transparency
+    pub fn with_version(version: GifVersion) -> Self {
+        Self {
+            version,
+            ..Self::default()
+        }
+    }
+
+    // min_code_size fixes the lzw minimum code size of every frame,
+    // see try_lzw_encode_with_code_size. by default the smallest that fits is used
+    pub fn min_code_size(mut self, size: u8) -> Self {
+        self.min_code_size = Some(size);
+        self
+    }
+
+    pub fn try_write_frame(
+        &mut self,
+        buf: &mut Vec<u8>,
+        frame: &Frame,
+        delay: u16,
+        interlaced: bool,
+        dispose: DisposalMethod,
+    ) -> Result<(), GifError> {
+        frame.validate()?;
+
+        self.lzw.clear();
+        let data = if interlaced {
+            self.rows.clear();
+            interlace_into(&mut self.rows, &frame.buffer, frame.width, frame.height);
+            &self.rows
+        } else {
+            &frame.buffer
+        };
+
+        let size = self.min_code_size.unwrap_or_else(|| min_code_size(data));
+        try_lzw_encode_with_code_size(&mut self.lzw, data, size)?;
+
+        match self.version {
+            GifVersion::Gif87a => GifEncoder::write_image_desc(buf, frame, interlaced),
+            GifVersion::Gif89a => {
+                GifEncoder::write_frame_header(buf, frame, delay, interlaced, dispose)
+            }
+        }
+        GifEncoder::write_encoded_image_block(buf, &self.lzw);
+        Ok(())
     }
 }
 
 pub struct Frame {
+    pub left: u16,
+    pub top: u16,
     pub width: u16,
     pub height: u16,
     pub transparent: Option<u8>,
@@ -174,6 +477,139 @@ pub struct Frame {
     pub buffer: Vec<u8>,
 }
 
+impl Frame {
+    // crops the frame to the smallest rectangle holding every pixel that isn't
+    // transparent, moving left and top so it still lands in the same place
+    // what's outside is left to the disposal of the previous frame
+    // a no-op for frames without a transparent index or transparent borders,
+    // a fully transparent frame shrinks to a single transparent pixel
+    pub fn trim_transparent(&mut self) {
+        let Some(t) = self.transparent else {
+            return;
+        };
+
+        let width = self.width as usize;
+        if width == 0 {
+            return;
+        }
+
+        let (mut min_x, mut min_y) = (usize::MAX, usize::MAX);
+        let (mut max_x, mut max_y) = (0, 0);
+        for (i, _) in self.buffer.iter().enumerate().filter(|(_, &p)| p != t) {
+            let (x, y) = (i % width, i / width);
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+
+        let (left, top, w, h) = match min_x {
+            usize::MAX => (0, 0, 1, 1),
+            _ => (min_x, min_y, max_x - min_x + 1, max_y - min_y + 1),
+        };
+
+        if (w, h) == (width, self.height as usize) {
+            return;
+        }
+
+        let mut buffer = Vec::with_capacity(w * h);
+        for y in top..top + h {
+            let start = y * width + left;
+            buffer.extend_from_slice(&self.buffer[start..start + w]);
+        }
+
+        self.left += left as u16;
+        self.top += top as u16;
+        self.width = w as u16;
+        self.height = h as u16;
+        self.buffer = buffer;
+    }
+
+    // checks that the frame can be encoded as a valid image block
+    pub fn validate(&self) -> Result<(), GifError> {
+        let len = self.buffer.len();
+        if self.width == 0 || self.height == 0 || len == 0 {
+            return Err(GifError::EmptyFrame);
+        }
+
+        if self.width as usize * self.height as usize != len {
+            return Err(GifError::InvalidDimensions {
+                width: self.width,
+                height: self.height,
+                len,
+            });
+        }
+
+        if let Some(palette) = &self.palette {
+            check_palette_len(palette)?;
+            let colors = palette.len() / 3;
+
+            if let Some(&index) = self.buffer.iter().find(|&&i| i as usize >= colors) {
+                return Err(GifError::IndexOutOfRange { index, colors });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// checks that data holds exactly w * h pixels of the given number of channels
+pub fn check_buffer_len(w: u16, h: u16, channels: usize, data: &[u8]) -> Result<(), GifError> {
+    let expected = w as usize * h as usize * channels;
+    if data.len() != expected {
+        return Err(GifError::BufferSizeMismatch {
+            expected,
+            got: data.len(),
+        });
+    }
+
+    Ok(())
+}
+
+// checks that an rgb palette fits in a gif color table
+fn check_palette_len(palette: &[u8]) -> Result<(), GifError> {
+    let colors = palette.len() / 3;
+    if colors > 256 {
+        return Err(GifError::PaletteTooLarge(colors));
+    }
+
+    Ok(())
+}
+
+// checks that gamma is a positive finite number
+fn check_gamma(gamma: f32) -> Result<(), GifError> {
+    if gamma.is_finite() && gamma > 0.0 {
+        Ok(())
+    } else {
+        Err(GifError::InvalidGamma)
+    }
+}
+
+// the frame constructors can't return errors, so mismatched buffers are only caught in debug builds
+fn debug_check_buffer_len(w: u16, h: u16, channels: usize, data: &[u8]) {
+    if let Err(e) = check_buffer_len(w, h, channels, data) {
+        debug_assert!(false, "{}", e);
+    }
+}
+
+// converts premultiplied rgba to straight alpha in place
+// pixels with an alpha of 0 have no color to recover and are left as is
+pub fn unpremultiply_alpha(data: &mut [u8]) {
+    for pix in data.chunks_exact_mut(4) {
+        let a = pix[3] as u32;
+        if a == 0 || a == 0xFF {
+            continue;
+        }
+
+        for c in &mut pix[..3] {
+            *c = ((*c as u32 * 255 + a / 2) / a).min(255) as u8;
+        }
+    }
+}
+
+// snaps every alpha that isn't 0 to 255 in place, the hard edged transparency a gif
+// can actually show. none of the constructors do this on their own, call it on
+// the data first or use GifStream::normalize_alpha
 pub fn normalize_alpha(data: &mut [u8]) {
     for pix in data.chunks_exact_mut(4) {
         if pix[3] != 0 {
@@ -182,54 +618,527 @@ pub fn normalize_alpha(data: &mut [u8]) {
     }
 }
 
+// speed is the sample factor of the quantizer (1 to 30), the palette is learned
+// from one in every `speed` pixels, so 1 is the slowest and most accurate
+// it only affects learning the palette, mapping the pixels onto it costs the same at any speed
+// PaletteOptions sets it on its own together with the quantizer and color count
 impl Frame {
     pub fn from_rgba(w: u16, h: u16, data: &[u8], speed: i32) -> Self {
-        let mut transparent = None;
+        Self::from_rgba_with_alpha_threshold(w, h, data, speed, 1)
+    }
 
-        for pix in data.chunks_exact(4) {
-            if pix[3] == 0 {
-                transparent = Some(pix);
+    // same as from_rgba, but errors on impossible geometry instead of building
+    // a frame that can't be encoded, e.g. a zero width or a buffer that's too small
+    pub fn try_from_rgba(w: u16, h: u16, data: &[u8], speed: i32) -> Result<Self, GifError> {
+        if w == 0 || h == 0 {
+            return Err(GifError::EmptyFrame);
+        }
+
+        check_buffer_len(w, h, 4, data)?;
+        Ok(Self::from_rgba(w, h, data, speed))
+    }
+
+    // same as from_rgba, but any pixel with an alpha below the threshold is
+    // treated as transparent (from_rgba uses a threshold of 1)
+    pub fn from_rgba_with_alpha_threshold(
+        w: u16,
+        h: u16,
+        data: &[u8],
+        speed: i32,
+        threshold: u8,
+    ) -> Self {
+        debug_check_buffer_len(w, h, 4, data);
+        let lp = LocalPalette::new(Quantizer::NeuQuant, speed, data, threshold);
+        Self::with_local_palette_rgba(w, h, data, &lp)
+    }
+
+    // same as from_rgba, but when the frame has at most 256 unique colors
+    // (counting transparency as one) the palette is built from those exact
+    // colors and no quantizer runs, so simple content stays pixel perfect
+    // the color table is sized to those colors, a 40 color frame gets 64 entries
+    // falls back to from_rgba when there are more colors
+    pub fn from_rgba_exact(w: u16, h: u16, data: &[u8], speed: i32) -> Self {
+        debug_check_buffer_len(w, h, 4, data);
+        let mut colors: HashMap<[u8; 3], u8> = HashMap::new();
+        let mut palette = Vec::new();
+        let has_transparency = data.chunks_exact(4).any(|pix| pix[3] == 0);
+        let limit = if has_transparency { 255 } else { 256 };
+
+        for pix in data.chunks_exact(4).filter(|pix| pix[3] != 0) {
+            let rgb = [pix[0], pix[1], pix[2]];
+            if !colors.contains_key(&rgb) {
+                if colors.len() == limit {
+                    return Self::from_rgba(w, h, data, speed);
+                }
+
+                colors.insert(rgb, colors.len() as u8);
+                palette.extend_from_slice(&rgb);
             }
         }
 
-        let nq = NeuQuant::new(speed, 256, &data);
-        let palette = nq.color_map_rgb();
+        let transparent = has_transparency.then(|| {
+            palette.extend_from_slice(&[0, 0, 0]); // transparent sentinel
+            colors.len() as u8
+        });
+
+        Self {
+            left: 0,
+            top: 0,
+            width: w,
+            height: h,
+            transparent,
+            palette: Some(palette),
+            buffer: data
+                .chunks_exact(4)
+                .map(|pix| match transparent {
+                    Some(t) if pix[3] == 0 => t,
+                    _ => colors[&[pix[0], pix[1], pix[2]]],
+                })
+                .collect(),
+        }
+    }
+
+    // same as from_rgba, but scales data from src_w x src_h to w x h first
+    // errors when data isn't exactly src_w * src_h rgba pixels
+    pub fn from_rgba_resized(
+        src_w: u16,
+        src_h: u16,
+        data: &[u8],
+        w: u16,
+        h: u16,
+        speed: i32,
+        filter: ResizeFilter,
+    ) -> Result<Self, GifError> {
+        if src_w == 0 || src_h == 0 || w == 0 || h == 0 {
+            return Err(GifError::EmptyFrame);
+        }
+
+        check_buffer_len(src_w, src_h, 4, data)?;
+
+        let resized = resize_rgba(data, src_w, src_h, w, h, filter);
+        Ok(Self::from_rgba(w, h, &resized, speed))
+    }
+
+    // same as from_rgba, for premultiplied rgba where the colors are already scaled by alpha
+    // the colors are divided back out before quantizing so anti-aliased edges
+    // don't turn dark, fully transparent pixels stay transparent
+    pub fn from_rgba_premultiplied(w: u16, h: u16, data: &[u8], speed: i32) -> Self {
+        debug_check_buffer_len(w, h, 4, data);
+        let mut data = data.to_vec();
+        unpremultiply_alpha(&mut data);
+        Self::from_rgba(w, h, &data, speed)
+    }
+
+    // same as from_rgba, but raises every channel to the power of gamma before
+    // quantizing and maps the learned palette back afterwards
+    // values below 1 give dark colors more room in the palette, above 1 bright ones
+    // 1 is the same as from_rgba, gamma that isn't positive and finite panics
+    pub fn from_rgba_with_gamma(w: u16, h: u16, data: &[u8], speed: i32, gamma: f32) -> Self {
+        if let Err(e) = check_gamma(gamma) {
+            panic!("{}", e);
+        }
+
+        debug_check_buffer_len(w, h, 4, data);
+        let mut frame = Self::from_rgba(w, h, &apply_gamma(data, &gamma_lut(gamma)), speed);
+        if let Some(palette) = &mut frame.palette {
+            let inverse = gamma_lut(1.0 / gamma);
+            palette.iter_mut().for_each(|c| *c = inverse[*c as usize]);
+        }
+
+        frame
+    }
+
+    // same as from_rgba_with_gamma, but errors instead of panicking and on
+    // impossible geometry like try_from_rgba
+    pub fn try_from_rgba_with_gamma(
+        w: u16,
+        h: u16,
+        data: &[u8],
+        speed: i32,
+        gamma: f32,
+    ) -> Result<Self, GifError> {
+        check_gamma(gamma)?;
+        if w == 0 || h == 0 {
+            return Err(GifError::EmptyFrame);
+        }
+
+        check_buffer_len(w, h, 4, data)?;
+        Ok(Self::from_rgba_with_gamma(w, h, data, speed, gamma))
+    }
+
+    // same as from_rgba, but maps the pixels onto the palette of any Quantize
+    // fully transparent pixels get an extra slot after the palette when it has
+    // fewer than 256 colors, otherwise they're mapped like any other pixel
+    pub fn from_rgba_quantized<Q: Quantize>(w: u16, h: u16, data: &[u8], quantizer: &Q) -> Self {
+        debug_check_buffer_len(w, h, 4, data);
+        let mut palette = quantizer.palette().to_vec();
+
+        let colors = palette.len() / 3;
+        let has_transparency = data.chunks_exact(4).any(|pix| pix[3] == 0);
+        let transparent = (has_transparency && colors < 256).then(|| {
+            palette.extend_from_slice(&[0, 0, 0]); // transparent sentinel
+            colors as u8
+        });
 
         Self {
+            left: 0,
+            top: 0,
             width: w,
             height: h,
-            transparent: transparent.map(|t| nq.index_of(t) as u8),
+            transparent,
             palette: Some(palette),
             buffer: data
                 .chunks_exact(4)
+                .map(|pix| match transparent {
+                    Some(t) if pix[3] == 0 => t,
+                    _ => quantizer.index_of(pix),
+                })
+                .collect(),
+        }
+    }
+
+    // same as from_rgba, but learns the palette with the given quantizer
+    pub fn from_rgba_with_quantizer(
+        w: u16,
+        h: u16,
+        data: &[u8],
+        speed: i32,
+        quantizer: Quantizer,
+    ) -> Self {
+        debug_check_buffer_len(w, h, 4, data);
+        let lp = LocalPalette::new(quantizer, speed, data, 1);
+        Self::with_local_palette_rgba(w, h, data, &lp)
+    }
+
+    // same as from_rgba, but the palette is learned as the options say
+    // the transparent slot counts towards the options' colors
+    pub fn from_rgba_with_options(w: u16, h: u16, data: &[u8], options: PaletteOptions) -> Self {
+        debug_check_buffer_len(w, h, 4, data);
+        let lp = LocalPalette::with_colors(
+            options.quantizer,
+            options.sample_factor,
+            options.colors,
+            data,
+            1,
+        );
+        Self::with_local_palette_rgba(w, h, data, &lp)
+    }
+
+    // same as from_rgba, but diffuses the quantization error to neighboring
+    // pixels (floyd-steinberg) to reduce banding on gradients
+    pub fn from_rgba_dithered(w: u16, h: u16, data: &[u8], speed: i32) -> Self {
+        Self::from_rgba_dithered_amount(w, h, data, speed, 1.0)
+    }
+
+    // same as from_rgba_dithered, but only diffuses `amount` of the error
+    // 0.0 is plain nearest color, 1.0 is full floyd-steinberg, out of range values are clamped
+    pub fn from_rgba_dithered_amount(w: u16, h: u16, data: &[u8], speed: i32, amount: f32) -> Self {
+        debug_check_buffer_len(w, h, 4, data);
+        let lp = LocalPalette::new(Quantizer::NeuQuant, speed, data, 1);
+        Self::with_local_palette_rgba_dithered(w, h, data, &lp, amount)
+    }
+
+    // same as from_rgba, but maps the pixels onto the learned palette in parallel
+    // learning the palette is still sequential, the lookup is what dominates large frames
+    #[cfg(feature = "rayon")]
+    pub fn from_rgba_parallel(w: u16, h: u16, data: &[u8], speed: i32) -> Self {
+        debug_check_buffer_len(w, h, 4, data);
+        use rayon::prelude::*;
+
+        let lp = LocalPalette::new(Quantizer::NeuQuant, speed, data, 1);
+        Self {
+            left: 0,
+            top: 0,
+            width: w,
+            height: h,
+            transparent: lp.transparent,
+            palette: Some(lp.palette.clone()),
+            buffer: data
+                .par_chunks_exact(4)
+                .map(|pix| lp.index_of(pix))
+                .collect(),
+        }
+    }
+
+    // maps a frame onto an already learned local palette
+    pub(crate) fn with_local_palette_rgba(w: u16, h: u16, data: &[u8], lp: &LocalPalette) -> Self {
+        debug_check_buffer_len(w, h, 4, data);
+        Self {
+            left: 0,
+            top: 0,
+            width: w,
+            height: h,
+            transparent: lp.transparent,
+            palette: Some(lp.palette.clone()),
+            buffer: data.chunks_exact(4).map(|pix| lp.index_of(pix)).collect(),
+        }
+    }
+
+    pub(crate) fn with_local_palette_rgba_dithered(
+        w: u16,
+        h: u16,
+        data: &[u8],
+        lp: &LocalPalette,
+        amount: f32,
+    ) -> Self {
+        debug_check_buffer_len(w, h, 4, data);
+        Self {
+            left: 0,
+            top: 0,
+            width: w,
+            height: h,
+            transparent: lp.transparent,
+            palette: Some(lp.palette.clone()),
+            buffer: dither_floyd_steinberg(w, data, &lp.palette, amount, |pix| lp.index_of(pix)),
+        }
+    }
+
+    // same as from_rgba, but offsets each pixel by a bayer threshold matrix
+    // before the palette lookup (ordered dithering)
+    // cheaper than floyd-steinberg and deterministic, matrix_size must be 4 or 8
+    pub fn from_rgba_ordered(w: u16, h: u16, data: &[u8], speed: i32, matrix_size: usize) -> Self {
+        debug_check_buffer_len(w, h, 4, data);
+        let matrix: &[u8] = match matrix_size {
+            4 => &BAYER_4,
+            8 => &BAYER_8,
+            _ => panic!("matrix_size must be 4 or 8"),
+        };
+
+        let lp = LocalPalette::new(Quantizer::NeuQuant, speed, data, 1);
+
+        let width = (w as usize).max(1);
+        let cells = (matrix_size * matrix_size) as i32;
+
+        Self {
+            left: 0,
+            top: 0,
+            width: w,
+            height: h,
+            transparent: lp.transparent,
+            buffer: data
+                .chunks_exact(4)
+                .enumerate()
+                .map(|(i, pix)| {
+                    if pix[3] == 0 {
+                        return lp.index_of(pix);
+                    }
+
+                    let (x, y) = (i % width, i / width);
+                    let m = matrix[(y % matrix_size) * matrix_size + x % matrix_size] as i32;
+
+                    // threshold centered around 0, spanning -16..16
+                    let offset = (2 * m + 1) * ORDERED_SPREAD / (2 * cells) - ORDERED_SPREAD / 2;
+                    let adjusted = [
+                        (pix[0] as i32 + offset).clamp(0, 255) as u8,
+                        (pix[1] as i32 + offset).clamp(0, 255) as u8,
+                        (pix[2] as i32 + offset).clamp(0, 255) as u8,
+                        pix[3],
+                    ];
+
+                    lp.index_of(&adjusted)
+                })
+                .collect(),
+            palette: Some(lp.palette),
+        }
+    }
+
+    // builds a partial frame covering only the pixels that changed between
+    // prev and data (both full w x h rgba buffers), positioned with left/top
+    // meant to be drawn over the previous frame with DisposalMethod::Keep
+    // prev must be what the viewer shows before this frame, after a Previous
+    // frame that's the canvas from before it, see DisposalMethod
+    // if nothing changed, a 1x1 frame redrawing the top left pixel is returned
+    pub fn from_rgba_delta(w: u16, h: u16, prev: &[u8], data: &[u8], speed: i32) -> Self {
+        debug_check_buffer_len(w, h, 4, data);
+        let (left, top, width, height) = changed_region(w, h, prev, data).unwrap_or((0, 0, 1, 1));
+        let cropped = crop_rgba(w, data, left, top, width, height);
+
+        let mut frame = Self::from_rgba(width, height, &cropped, speed);
+        frame.left = left;
+        frame.top = top;
+        frame
+    }
+
+    // builds a full size frame where every pixel that is unchanged from prev
+    // is set to the transparent index, so the static parts collapse into long
+    // lzw runs. meant to be drawn over the previous frame with DisposalMethod::Keep
+    // like from_rgba_delta, prev is what the viewer shows before this frame
+    // transparent pixels in data that changed can't clear what's beneath them
+    pub fn from_rgba_diffed(w: u16, h: u16, prev: &[u8], data: &[u8], speed: i32) -> Self {
+        debug_check_buffer_len(w, h, 4, data);
+        Self::from_rgba(w, h, &mask_unchanged(prev, data), speed)
+    }
+
+    // same as from_rgba, but for 16 bits per channel rgba
+    // each channel is rounded to the nearest 8 bit value before quantizing
+    pub fn from_rgba16(w: u16, h: u16, data: &[u16], speed: i32) -> Self {
+        Self::from_rgba(w, h, &rgba16_to_rgba(w, data, false), speed)
+    }
+
+    // same as from_rgba16, but dithers the reduction to 8 bits with a bayer matrix
+    // so smooth 16 bit gradients don't band before the palette is even learned
+    // alpha is always rounded, it only decides what's transparent
+    pub fn from_rgba16_dithered(w: u16, h: u16, data: &[u16], speed: i32) -> Self {
+        Self::from_rgba(w, h, &rgba16_to_rgba(w, data, true), speed)
+    }
+
+    // same as from_rgba, but for tightly packed rgb data (no alpha)
+    // frames built from rgb data never have a transparent index
+    pub fn from_rgb(w: u16, h: u16, data: &[u8], speed: i32) -> Self {
+        debug_check_buffer_len(w, h, 3, data);
+        let nq = neuquant_rgb(speed, 256, data);
+        let palette = nq.color_map_rgb();
+
+        Self {
+            left: 0,
+            top: 0,
+            width: w,
+            height: h,
+            transparent: None,
+            palette: Some(palette),
+            buffer: data
+                .chunks_exact(3)
                 .map(|pix| nq.index_of(pix) as u8)
                 .collect(),
         }
     }
 
+    // same as from_rgb, but the palette entry the key color quantizes to
+    // becomes the transparent index (chroma keying)
+    // a gif only has one transparent index, so colors close enough to the key
+    // to share its palette entry turn transparent as well
+    pub fn from_rgb_with_colorkey(w: u16, h: u16, data: &[u8], speed: i32, key: [u8; 3]) -> Self {
+        debug_check_buffer_len(w, h, 3, data);
+        let nq = neuquant_rgb(speed, 256, data);
+        let key = nq.index_of(&key) as u8;
+
+        let buffer: Vec<u8> = data
+            .chunks_exact(3)
+            .map(|pix| nq.index_of(pix) as u8)
+            .collect();
+
+        Self {
+            left: 0,
+            top: 0,
+            width: w,
+            height: h,
+            transparent: buffer.contains(&key).then_some(key),
+            palette: Some(nq.color_map_rgb()),
+            buffer,
+        }
+    }
+
+    // same as from_rgba, but for data in b, g, r, a byte order
+    // the palette written out is still rgb, as the gif spec requires
+    pub fn from_bgra(w: u16, h: u16, data: &[u8], speed: i32) -> Self {
+        Self::from_rgba(w, h, &swap_red_blue(data, 4), speed)
+    }
+
+    // same as from_rgb, but for data in b, g, r byte order
+    // the palette written out is still rgb, as the gif spec requires
+    pub fn from_bgr(w: u16, h: u16, data: &[u8], speed: i32) -> Self {
+        Self::from_rgb(w, h, &swap_red_blue(data, 3), speed)
+    }
+
+    // builds a frame from single channel 8-bit luma data
+    // uses a fixed grayscale ramp as the palette (no quantization), so each
+    // byte is its own index and the output is exact
+    // the ramp only extends up to the brightest level present
+    pub fn from_luma(w: u16, h: u16, data: &[u8]) -> Self {
+        debug_check_buffer_len(w, h, 1, data);
+        let max = data.iter().copied().max().unwrap_or(0);
+        let palette = (0..=max).flat_map(|l| [l, l, l]).collect();
+
+        Self {
+            left: 0,
+            top: 0,
+            width: w,
+            height: h,
+            transparent: None,
+            palette: Some(palette),
+            buffer: data.to_vec(),
+        }
+    }
+
     pub fn with_global_palette_rgba(w: u16, h: u16, data: &[u8], gp: &GlobalPalette) -> Self {
-        let mut transparent = None;
+        debug_check_buffer_len(w, h, 4, data);
+        let mut buffer: Vec<u8> = data.chunks_exact(4).map(|pix| gp.index_of(pix)).collect();
+        let has_transparency = data.chunks_exact(4).any(|pix| pix[3] == 0);
 
-        for pix in data.chunks_exact(4) {
-            if pix[3] == 0 {
-                transparent = Some(pix);
+        let transparent = match gp.transparent {
+            Some(t) => has_transparency.then_some(t),
+            // without a reserved slot, an index no opaque pixel maps to stands in for
+            // transparency, so opaque pixels never turn transparent along with it
+            // when every index is taken, transparent pixels keep their nearest color
+            None if has_transparency => {
+                let mut used = [false; 256];
+                for (pix, &i) in data.chunks_exact(4).zip(&buffer) {
+                    if pix[3] != 0 {
+                        used[i as usize] = true;
+                    }
+                }
+
+                let free = (0..gp.palette.len() / 3)
+                    .find(|&i| !used[i])
+                    .map(|i| i as u8);
+                if let Some(t) = free {
+                    for (pix, i) in data.chunks_exact(4).zip(&mut buffer) {
+                        if pix[3] == 0 {
+                            *i = t;
+                        }
+                    }
+                }
+
+                free
             }
+            None => None,
+        };
+
+        Self {
+            left: 0,
+            top: 0,
+            width: w,
+            height: h,
+            transparent,
+            palette: None,
+            buffer,
         }
+    }
 
+    pub fn with_global_palette_rgb(w: u16, h: u16, data: &[u8], gp: &GlobalPalette) -> Self {
+        debug_check_buffer_len(w, h, 3, data);
         Self {
+            left: 0,
+            top: 0,
             width: w,
             height: h,
-            transparent: transparent.map(|t| gp.index_of(t) as u8),
+            transparent: None,
             palette: None,
-            buffer: data
-                .chunks_exact(4)
-                .map(|pix| gp.index_of(pix) as u8)
-                .collect(),
+            buffer: gp.index_buffer_rgb(data),
+        }
+    }
+
+    // a frame of nothing but the transparent index, no quantization involved
+    // written with DisposalMethod::Background (e.g. from stream_frames_disposed)
+    // it blanks the canvas between segments without sending an opaque frame
+    // the local color table is just big enough to hold the index
+    pub fn transparent(w: u16, h: u16, transparent_index: u8) -> Self {
+        Self {
+            left: 0,
+            top: 0,
+            width: w,
+            height: h,
+            transparent: Some(transparent_index),
+            palette: Some(vec![0; (transparent_index as usize + 1) * 3]),
+            buffer: vec![transparent_index; w as usize * h as usize],
         }
     }
 
     pub fn from_palatte_rgba(w: u16, h: u16, data: &[u8], palette: &[u8]) -> Self {
+        debug_check_buffer_len(w, h, 1, data);
         Self {
+            left: 0,
+            top: 0,
             width: w,
             height: h,
             transparent: None,
@@ -239,7 +1148,10 @@ impl Frame {
     }
 
     pub fn from_indexed_rgba(w: u16, h: u16, data: &[u8]) -> Self {
+        debug_check_buffer_len(w, h, 1, data);
         Self {
+            left: 0,
+            top: 0,
             width: w,
             height: h,
             transparent: None,
@@ -249,22 +1161,118 @@ impl Frame {
     }
 }
 
-#[derive(Copy, Clone)]
+#[cfg(feature = "image")]
+impl Frame {
+    // builds a frame from an image::RgbaImage, see from_rgba
+    // fails if the image is wider or taller than u16::MAX
+    pub fn from_image(img: &image::RgbaImage, speed: i32) -> Result<Self, TryFromIntError> {
+        let w = u16::try_from(img.width())?;
+        let h = u16::try_from(img.height())?;
+
+        Ok(Self::from_rgba(w, h, img.as_raw(), speed))
+    }
+}
+
+#[cfg(feature = "image")]
+impl TryFrom<&image::RgbaImage> for Frame {
+    type Error = TryFromIntError;
+
+    // uses the default speed of 10
+    fn try_from(img: &image::RgbaImage) -> Result<Self, Self::Error> {
+        Self::from_image(img, 10)
+    }
+}
+
+// What a decoder does with a frame before drawing the next one
+// Any leaves it unspecified, which suits independent full frames, Keep draws the
+// next frame over this one. either way only pixels at a frame's transparent index
+// (if it has one) let what's underneath show through
+// Previous restores the frame's area to what it was before the frame was drawn,
+// so the next frame lands on that older canvas and not on this frame. the encoder
+// keeps no canvas of its own, frames diffed against each other (from_rgba_diffed,
+// from_rgba_delta) have to be diffed against that restored canvas by the caller,
+// which is why the diffed streams always write Keep
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
 pub enum DisposalMethod {
+    #[default]
     Any = 0,
     Keep = 1,
     Background = 2,
     Previous = 3,
 }
 
+// The signature written at the start of a gif
+// GIF87a is only there for old decoders, it has no extensions at all
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GifVersion {
+    Gif87a,
+    #[default]
+    Gif89a,
+}
+
+impl GifVersion {
+    pub fn signature(&self) -> &'static [u8; 6] {
+        match self {
+            GifVersion::Gif87a => b"GIF87a",
+            GifVersion::Gif89a => b"GIF89a",
+        }
+    }
+}
+
+// how many times a gif should loop, written as a NETSCAPE2.0 loop count
+// Infinite loops forever (a loop count of 0 in the file, which is why 0 isn't a count here)
+// Finite(n) plays the animation once and then repeats it n more times,
+// so Finite(0) plays it once and writes no extension at all
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Repeat {
+    Infinite,
+    Finite(u16),
+}
+
 pub enum ExtensionData {
     Control {
         flags: u8,
         delay: u16,
         transparency_idx: u8,
     },
+    #[deprecated(note = "use GifEncoder::write_loop_control with Repeat::Finite")]
     Repetitions(u16),
+    #[deprecated(note = "use GifEncoder::write_loop_control with Repeat::Infinite")]
     InfiniteRepetitions,
+    Comment(String),
+    Application {
+        identifier: [u8; 8],
+        auth_code: [u8; 3],
+        data: Vec<u8>,
+    },
+}
+
+// NETSCAPE2.0 looping sub-block, a repeat count of 0 loops forever
+fn netscape_loop(repeat: u16) -> ExtensionData {
+    let [lo, hi] = repeat.to_le_bytes();
+    ExtensionData::Application {
+        identifier: *b"NETSCAPE",
+        auth_code: *b"2.0",
+        data: vec![1, lo, hi],
+    }
+}
+
+// Writes data as length prefixed sub-blocks of at most 255 bytes
+// the terminating zero length block is left to the caller
+fn write_sub_blocks_to<W: Write>(w: &mut W, data: &[u8]) -> io::Result<()> {
+    let mut iter = data.chunks_exact(0xFF);
+    for chunk in iter.by_ref() {
+        w.write_all(&[0xFF])?;
+        w.write_all(chunk)?;
+    }
+
+    let rem = iter.remainder();
+    if !rem.is_empty() {
+        w.write_all(&[rem.len() as u8])?;
+        w.write_all(rem)?;
+    }
+
+    Ok(())
 }
 
 // Color table size converted to flag bits
@@ -282,45 +1290,589 @@ fn flag_size(size: usize) -> u8 {
     }
 }
 
+// A palette learned from a single rgba frame
+// when any pixel is below the alpha threshold, the quantizer learns one color
+// less and the last slot is reserved for transparency, so the transparent
+// index never aliases an opaque color and doesn't depend on scan order
+pub(crate) struct LocalPalette {
+    quantized: Quantized,
+    palette: Vec<u8>,
+    transparent: Option<u8>,
+    threshold: u8,
+}
+
+impl LocalPalette {
+    pub(crate) fn new(quantizer: Quantizer, speed: i32, data: &[u8], threshold: u8) -> Self {
+        Self::with_colors(quantizer, speed, 256, data, threshold)
+    }
+
+    // same as new, but learns at most `colors` colors (2 to 256), transparency included
+    pub(crate) fn with_colors(
+        quantizer: Quantizer,
+        speed: i32,
+        colors: usize,
+        data: &[u8],
+        threshold: u8,
+    ) -> Self {
+        let colors = colors.clamp(2, 256);
+        let has_transparency = data.chunks_exact(4).any(|pix| pix[3] < threshold);
+
+        if has_transparency {
+            let quantized = Quantized::new(quantizer, speed, colors - 1, data);
+            let mut palette = quantized.color_map_rgb();
+            palette.extend_from_slice(&[0, 0, 0]); // transparent sentinel
+
+            Self {
+                quantized,
+                transparent: Some((palette.len() / 3 - 1) as u8),
+                palette,
+                threshold,
+            }
+        } else {
+            let quantized = Quantized::new(quantizer, speed, colors, data);
+            let palette = quantized.color_map_rgb();
+
+            Self {
+                quantized,
+                palette,
+                transparent: None,
+                threshold,
+            }
+        }
+    }
+
+    // whether every pixel of data can be represented by this palette
+    // a palette without a transparent slot can't represent transparent pixels
+    pub(crate) fn covers(&self, data: &[u8]) -> bool {
+        self.transparent.is_some() || data.chunks_exact(4).all(|pix| pix[3] >= self.threshold)
+    }
+
+    fn index_of(&self, pix: &[u8]) -> u8 {
+        match self.transparent {
+            Some(t) if pix[3] < self.threshold => t,
+            _ => self.quantized.index_of(pix),
+        }
+    }
+}
+
 pub struct GlobalPalette {
-    nq: NeuQuant,
+    quantized: Quantized,
     palette: Vec<u8>,
+    transparent: Option<u8>,
+    gamma: Option<Box<[u8; 256]>>,
 }
 
+// like Frame, speed is only the sample factor used to learn the palette
+// a palette learned once at speed 1 indexes frames just as fast as one learned at 30
 impl GlobalPalette {
     // colors must be between 1 and 256
     pub fn new(speed: i32, colors: usize, data: &[u8]) -> Self {
-        assert!(colors > 0 && colors <= 256);
+        match Self::try_new(speed, colors, data) {
+            Ok(gp) => gp,
+            Err(e) => panic!("{}", e),
+        }
+    }
 
-        let nq = NeuQuant::new(speed, colors, data);
-        let palette = nq.color_map_rgb();
+    // same as new, but returns an error instead of panicking
+    // speed must be between 1 and 30
+    pub fn try_new(speed: i32, colors: usize, data: &[u8]) -> Result<Self, GifError> {
+        Self::try_with_quantizer(Quantizer::NeuQuant, speed, colors, data)
+    }
+
+    // same as try_new, but learns the palette with the given quantizer
+    pub fn try_with_quantizer(
+        quantizer: Quantizer,
+        speed: i32,
+        colors: usize,
+        data: &[u8],
+    ) -> Result<Self, GifError> {
+        if !(1..=30).contains(&speed) {
+            return Err(GifError::InvalidSpeed(speed));
+        }
+
+        if !(1..=256).contains(&colors) {
+            return Err(GifError::InvalidColorCount(colors));
+        }
+
+        let quantized = Quantized::new(quantizer, speed, colors, data);
+        let palette = quantized.color_map_rgb();
+
+        Ok(Self {
+            quantized,
+            palette,
+            transparent: None,
+            gamma: None,
+        })
+    }
+
+    // learns the palette as the options say, they are checked when they're set
+    pub fn with_options(data: &[u8], options: PaletteOptions) -> Self {
+        let quantized = Quantized::new(
+            options.quantizer,
+            options.sample_factor,
+            options.colors,
+            data,
+        );
+
+        Self {
+            palette: quantized.color_map_rgb(),
+            quantized,
+            transparent: None,
+            gamma: None,
+        }
+    }
+
+    // same as try_new, but learns the palette with every channel raised to the
+    // power of gamma, see Frame::from_rgba_with_gamma
+    // pixels are mapped through the same curve when they are indexed
+    // errors with InvalidGamma when gamma isn't positive and finite
+    pub fn try_with_gamma(
+        speed: i32,
+        colors: usize,
+        data: &[u8],
+        gamma: f32,
+    ) -> Result<Self, GifError> {
+        check_gamma(gamma)?;
+        let lut = gamma_lut(gamma);
+        let mut gp = Self::try_new(speed, colors, &apply_gamma(data, &lut))?;
+
+        let inverse = gamma_lut(1.0 / gamma);
+        gp.palette
+            .iter_mut()
+            .for_each(|c| *c = inverse[*c as usize]);
+        gp.gamma = Some(Box::new(lut));
+
+        Ok(gp)
+    }
+
+    // same as try_with_quantizer, but learns colors - 1 entries and reserves
+    // the last index for fully transparent pixels
+    // colors must be between 2 and 256
+    pub fn try_with_transparency(
+        quantizer: Quantizer,
+        speed: i32,
+        colors: usize,
+        data: &[u8],
+    ) -> Result<Self, GifError> {
+        if !(2..=256).contains(&colors) {
+            return Err(GifError::InvalidTransparentColorCount(colors));
+        }
+
+        let mut gp = Self::try_with_quantizer(quantizer, speed, colors - 1, data)?;
+        gp.transparent = Some((gp.palette.len() / 3) as u8);
+        gp.palette.extend_from_slice(&[0, 0, 0]); // transparent sentinel
+
+        Ok(gp)
+    }
+
+    // same as new, but learns the palette from several frames at once
+    // so it covers the colors of the whole animation, not just the first frame
+    pub fn from_samples(speed: i32, colors: usize, frames: &[&[u8]]) -> Self {
+        match Self::try_from_samples(speed, colors, frames) {
+            Ok(gp) => gp,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    // same as from_samples, but returns an error instead of panicking
+    pub fn try_from_samples(speed: i32, colors: usize, frames: &[&[u8]]) -> Result<Self, GifError> {
+        Self::try_with_quantizer(Quantizer::NeuQuant, speed, colors, &frames.concat())
+    }
 
-        Self { nq, palette }
+    // the 216 color web safe palette plus a transparency slot
+    // maps pixels by rounding each channel to the nearest of
+    // 0, 51, 102, 153, 204 and 255, no quantization involved
+    // fully transparent pixels map to the transparency slot (index 216)
+    pub fn web_safe() -> Self {
+        let quantized = Quantized::WebSafe;
+        let palette = quantized.color_map_rgb();
+
+        Self {
+            quantized,
+            palette,
+            transparent: Some(WEB_SAFE_TRANSPARENT),
+            gamma: None,
+        }
     }
 
     pub fn palette(&self) -> &[u8] {
         &self.palette
     }
 
+    // the index reserved for fully transparent pixels, if any
+    pub fn transparent(&self) -> Option<u8> {
+        self.transparent
+    }
+
     pub fn index_of(&self, pix: &[u8]) -> u8 {
-        self.nq.index_of(pix) as u8
+        match (self.transparent, &self.gamma) {
+            (Some(t), _) if pix[3] == 0 => t,
+            (_, Some(lut)) => {
+                let pix = [
+                    lut[pix[0] as usize],
+                    lut[pix[1] as usize],
+                    lut[pix[2] as usize],
+                    pix[3],
+                ];
+                self.quantized.index_of(&pix)
+            }
+            _ => self.quantized.index_of(pix),
+        }
+    }
+
+    pub fn index_of_rgb(&self, pix: &[u8]) -> u8 {
+        self.index_of(&[pix[0], pix[1], pix[2], 0xFF])
     }
 
     pub fn get_indexed_rgba(&self, data: &[u8]) -> Vec<u8> {
         data.chunks_exact(4).map(|pix| self.index_of(pix)).collect()
     }
+
+    // same as get_indexed_rgba, for rgb data
+    pub fn index_buffer_rgb(&self, data: &[u8]) -> Vec<u8> {
+        data.chunks_exact(3)
+            .map(|pix| self.index_of_rgb(pix))
+            .collect()
+    }
+
+    // same as get_indexed_rgba, but replaces the contents of out instead of
+    // allocating, so one buffer can be reused frame after frame
+    pub fn index_buffer_into(&self, data: &[u8], out: &mut Vec<u8>) {
+        out.clear();
+        out.extend(data.chunks_exact(4).map(|pix| self.index_of(pix)));
+    }
+
+    // mean squared error per channel between the rgba pixels of data and the
+    // palette colors they map to, 0.0 is lossless and 65025.0 the worst possible
+    // fully transparent pixels are skipped, costs a second pass over the data
+    // so it's only worked out on request, e.g. to decide whether more colors are worth it
+    pub fn mean_squared_error(&self, data: &[u8]) -> f64 {
+        let mut sum = 0u64;
+        let mut samples = 0u64;
+        for pix in data.chunks_exact(4).filter(|pix| pix[3] != 0) {
+            let i = self.index_of(pix) as usize * 3;
+            for (&c, &p) in pix[..3].iter().zip(&self.palette[i..i + 3]) {
+                let d = c as i64 - p as i64;
+                sum += (d * d) as u64;
+            }
+            samples += 3;
+        }
+
+        if samples == 0 {
+            return 0.0;
+        }
+
+        sum as f64 / samples as f64
+    }
+
+    // same as get_indexed_rgba, but looks the pixels up in parallel
+    #[cfg(feature = "rayon")]
+    pub fn get_indexed_rgba_parallel(&self, data: &[u8]) -> Vec<u8> {
+        use rayon::prelude::*;
+
+        data.par_chunks_exact(4)
+            .map(|pix| self.index_of(pix))
+            .collect()
+    }
+}
+
+// Swaps the red and blue channels of every pixel,
+// converting between bgr(a) and rgb(a)
+fn swap_red_blue(data: &[u8], channels: usize) -> Vec<u8> {
+    let mut swapped = data[..data.len() / channels * channels].to_vec();
+    for pix in swapped.chunks_exact_mut(channels) {
+        pix.swap(0, 2);
+    }
+    swapped
+}
+
+// Maps every channel value c to 255 * (c / 255)^gamma
+fn gamma_lut(gamma: f32) -> [u8; 256] {
+    let mut lut = [0; 256];
+    for (c, v) in lut.iter_mut().enumerate() {
+        *v = ((c as f32 / 255.0).powf(gamma) * 255.0).round() as u8;
+    }
+
+    lut
+}
+
+// Applies a gamma lookup table to the color channels of rgba data, alpha is left alone
+fn apply_gamma(data: &[u8], lut: &[u8; 256]) -> Vec<u8> {
+    let mut out = data.to_vec();
+    for pix in out.chunks_exact_mut(4) {
+        for c in &mut pix[..3] {
+            *c = lut[*c as usize];
+        }
+    }
+
+    out
+}
+
+// Reduces 16 bit rgba to 8 bits per channel, rounding or with an ordered dither
+// of the color channels, the dither threshold spans a whole 8 bit step
+fn rgba16_to_rgba(w: u16, data: &[u16], dither: bool) -> Vec<u8> {
+    const MAX: u32 = u16::MAX as u32;
+    let width = (w as usize).max(1);
+
+    let mut rgba = Vec::with_capacity(data.len());
+    for (i, pix) in data.chunks_exact(4).enumerate() {
+        let threshold = if dither {
+            let (x, y) = (i % width, i / width);
+            let m = BAYER_4[(y % 4) * 4 + x % 4] as u32;
+            (2 * m + 1) * MAX / 32
+        } else {
+            MAX / 2
+        };
+
+        for &c in &pix[..3] {
+            rgba.push(((c as u32 * 255 + threshold) / MAX) as u8);
+        }
+        rgba.push(((pix[3] as u32 * 255 + MAX / 2) / MAX) as u8);
+    }
+    rgba
+}
+
+// Bayer threshold matrices used for ordered dithering
+const BAYER_4: [u8; 16] = [
+    0, 8, 2, 10, //
+    12, 4, 14, 6, //
+    3, 11, 1, 9, //
+    15, 7, 13, 5,
+];
+
+const BAYER_8: [u8; 64] = [
+    0, 32, 8, 40, 2, 34, 10, 42, //
+    48, 16, 56, 24, 50, 18, 58, 26, //
+    12, 44, 4, 36, 14, 46, 6, 38, //
+    60, 28, 52, 20, 62, 30, 54, 22, //
+    3, 35, 11, 43, 1, 33, 9, 41, //
+    51, 19, 59, 27, 49, 17, 57, 25, //
+    15, 47, 7, 39, 13, 45, 5, 37, //
+    63, 31, 55, 23, 61, 29, 53, 21,
+];
+
+// Range of the ordered dithering offset, roughly the distance between
+// neighboring levels of a 256 color palette
+const ORDERED_SPREAD: i32 = 32;
+
+// Maps each pixel to a palette index while spreading the quantization error
+// to the right and lower neighbors. Errors never cross row boundaries and
+// fully transparent pixels neither receive nor spread error
+fn dither_floyd_steinberg(
+    width: u16,
+    data: &[u8],
+    palette: &[u8],
+    amount: f32,
+    index_of: impl Fn(&[u8]) -> u8,
+) -> Vec<u8> {
+    let width = width as usize;
+    let mut buffer = Vec::with_capacity(data.len() / 4);
+
+    // fraction of the error that is diffused, in 256ths
+    let amount = if amount.is_nan() {
+        0.0
+    } else {
+        amount.clamp(0.0, 1.0)
+    };
+    let amount = (amount * 256.0) as i32;
+
+    if width == 0 {
+        return buffer;
+    }
+
+    // error for the current and next row, padded by one pixel on each side
+    let mut current = vec![[0i32; 3]; width + 2];
+    let mut next = vec![[0i32; 3]; width + 2];
+
+    for row in data.chunks(width * 4) {
+        for (x, pix) in row.chunks_exact(4).enumerate() {
+            if pix[3] == 0 {
+                buffer.push(index_of(pix));
+                continue;
+            }
+
+            let err = current[x + 1];
+            let adjusted = [
+                (pix[0] as i32 + err[0] / 16).clamp(0, 255) as u8,
+                (pix[1] as i32 + err[1] / 16).clamp(0, 255) as u8,
+                (pix[2] as i32 + err[2] / 16).clamp(0, 255) as u8,
+                pix[3],
+            ];
+
+            let idx = index_of(&adjusted);
+            buffer.push(idx);
+
+            let color = &palette[idx as usize * 3..idx as usize * 3 + 3];
+            for c in 0..3 {
+                let e = (adjusted[c] as i32 - color[c] as i32) * amount / 256;
+
+                if x + 1 < width {
+                    current[x + 2][c] += e * 7;
+                    next[x + 2][c] += e;
+                }
+                if x > 0 {
+                    next[x][c] += e * 3;
+                }
+                next[x + 1][c] += e * 5;
+            }
+        }
+
+        std::mem::swap(&mut current, &mut next);
+        next.iter_mut().for_each(|e| *e = [0; 3]);
+    }
+
+    buffer
+}
+
+// Bounding box (left, top, width, height) of the pixels that differ between
+// two w x h rgba buffers, None if they are identical
+pub fn changed_region(w: u16, h: u16, prev: &[u8], data: &[u8]) -> Option<(u16, u16, u16, u16)> {
+    let width = w as usize;
+    let (mut min_x, mut min_y) = (w, h);
+    let (mut max_x, mut max_y) = (0, 0);
+    let mut changed = false;
+
+    if width == 0 {
+        return None;
+    }
+
+    for (i, (a, b)) in prev.chunks_exact(4).zip(data.chunks_exact(4)).enumerate() {
+        if a != b {
+            let (x, y) = ((i % width) as u16, (i / width) as u16);
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+            changed = true;
+        }
+    }
+
+    changed.then(|| (min_x, min_y, max_x - min_x + 1, max_y - min_y + 1))
+}
+
+// Copies a rectangle out of a w pixel wide rgba buffer
+pub fn crop_rgba(w: u16, data: &[u8], left: u16, top: u16, width: u16, height: u16) -> Vec<u8> {
+    let stride = w as usize * 4;
+    let mut out = Vec::with_capacity(width as usize * height as usize * 4);
+
+    for y in top as usize..top as usize + height as usize {
+        let start = y * stride + left as usize * 4;
+        out.extend_from_slice(&data[start..start + width as usize * 4]);
+    }
+
+    out
+}
+
+// A copy of data with every pixel that is the same in prev made fully transparent
+pub(crate) fn mask_unchanged(prev: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut masked = data.to_vec();
+    for (pix, prev) in masked.chunks_exact_mut(4).zip(prev.chunks_exact(4)) {
+        if pix == prev {
+            pix[3] = 0;
+        }
+    }
+
+    masked
+}
+
+// Reorders the rows of an indexed buffer into gif interlace pass order
+// pass 1: every 8th row from 0, pass 2: every 8th row from 4
+// pass 3: every 4th row from 2, pass 4: every 2nd row from 1
+pub fn interlace(data: &[u8], width: u16, height: u16) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    interlace_into(&mut out, data, width, height);
+    out
+}
+
+// same as interlace, but appends the reordered rows to out
+fn interlace_into(out: &mut Vec<u8>, data: &[u8], width: u16, height: u16) {
+    let width = width as usize;
+    let height = height as usize;
+
+    if width == 0 {
+        out.extend_from_slice(data);
+        return;
+    }
+
+    for (start, step) in [(0, 8), (4, 8), (2, 4), (1, 2)] {
+        for row in (start..height).step_by(step) {
+            if let Some(row) = data.get(row * width..(row + 1) * width) {
+                out.extend_from_slice(row);
+            }
+        }
+    }
+}
+
+// an upper bound on the bytes lzw_encode appends for data, to reserve buffers with
+// every index is assumed to need its own 12 bit code, plus the clear codes
+// the encoder emits whenever its dictionary fills up, so it's never too small
+pub fn lzw_encoded_len_estimate(data: &[u8]) -> usize {
+    let codes = data.len() + 2 + data.len() / 2048;
+    1 + (codes * 3).div_ceil(2)
 }
 
 pub fn lzw_encode(buf: &mut Vec<u8>, data: &[u8]) {
-    let min_code_size = match flag_size(1 + data.iter().copied().max().unwrap_or(0) as usize) + 1 {
+    let _ = try_lzw_encode(buf, data);
+}
+
+// same as lzw_encode, but returns the encoder's error instead of ignoring it
+pub fn try_lzw_encode(buf: &mut Vec<u8>, data: &[u8]) -> Result<(), GifError> {
+    try_lzw_encode_with_code_size(buf, data, min_code_size(data))
+}
+
+// the smallest lzw code size that fits every index in data
+fn min_code_size(data: &[u8]) -> u8 {
+    min_code_size_for_palette(1 + data.iter().copied().max().unwrap_or(0) as usize)
+}
+
+// the lzw code size for any frame indexing a palette of num_colors colors (up to 256)
+// unlike the one lzw_encode picks from the indices a frame happens to use, it's
+// the same for every frame, see try_write_image_block_with_code_size
+pub fn min_code_size_for_palette(num_colors: usize) -> u8 {
+    match flag_size(num_colors) + 1 {
         1 => 2, // As per gif spec: The minimal code size has to be >= 2
         n => n,
-    };
+    }
+}
 
+// same as try_lzw_encode, but with a fixed minimum code size instead of the
+// smallest one that fits, e.g. 8 for frames that use a full 256 color palette
+// the gif spec only allows sizes from 2 to 8, and every index in data has to
+// fit in it, anything else errors. larger sizes cost a little space per code
+// but are always valid, some decoders have fast paths for 8
+pub fn try_lzw_encode_with_code_size(
+    buf: &mut Vec<u8>,
+    data: &[u8],
+    min_code_size: u8,
+) -> Result<(), GifError> {
+    if !(2..=8).contains(&min_code_size) || min_code_size < self::min_code_size(data) {
+        return Err(GifError::InvalidCodeSize(min_code_size));
+    }
+
+    // buf may already hold earlier output, only what this call wrote is measured
+    let start = buf.len();
     buf.push(min_code_size);
 
     let mut encoder = LzwEncoder::new(BitOrder::Lsb, min_code_size);
-    let len = encoder.into_vec(buf).encode_all(data).consumed_out;
+    let result = encoder.into_vec(buf).encode_all(data);
+
+    buf.truncate(start + 1 + result.consumed_out);
+    result.status.map(|_| ()).map_err(GifError::Lzw)
+}
+
+// decodes data written by lzw_encode (the minimum code size, then the codes)
+// back to indices, e.g. to check that an encoded frame round trips
+pub fn lzw_decode(data: &[u8]) -> Result<Vec<u8>, GifError> {
+    let (&min_code_size, codes) = data.split_first().ok_or(GifError::InvalidCodeSize(0))?;
+    if !(2..=8).contains(&min_code_size) {
+        return Err(GifError::InvalidCodeSize(min_code_size));
+    }
+
+    let mut out = Vec::new();
+    let mut decoder = LzwDecoder::new(BitOrder::Lsb, min_code_size);
+    let result = decoder.into_vec(&mut out).decode_all(codes);
+    result.status.map_err(GifError::Lzw)?;
 
-    buf.truncate(len + 1);
+    out.truncate(result.consumed_out);
+    Ok(out)
 }
@@ -0,0 +1,88 @@
+// web framework integrations, each gated behind its own feature
+// every response applies GIF_HEADERS and streams the frames as they're encoded
+// headers are sent before the first frame, so a failing generator can't turn
+// into an error status, it ends the response body instead
+#[cfg(feature = "axum")]
+mod axum_response {
+    use std::fmt;
+
+    use axum::{
+        body::StreamBody,
+        response::{IntoResponse, Response},
+    };
+    use futures::Future;
+
+    use crate::{GifStream, GIF_HEADERS};
+
+    impl<S, F, D, E, R> IntoResponse for GifStream<S, F>
+    where
+        S: Clone + Send + 'static,
+        F: Fn(S) -> R + Send + 'static,
+        R: Future<Output = Result<D, E>> + Send + 'static,
+        D: AsRef<[u8]> + Send + 'static,
+        E: fmt::Debug + fmt::Display + Send + Sync + 'static,
+    {
+        fn into_response(self) -> Response {
+            (GIF_HEADERS, StreamBody::new(self.stream())).into_response()
+        }
+    }
+}
+
+#[cfg(feature = "actix")]
+mod actix_response {
+    use std::fmt;
+
+    use actix_web::{body::BoxBody, web::Bytes, HttpRequest, HttpResponse, Responder};
+    use futures::{Future, StreamExt};
+
+    use crate::{GifStream, GIF_HEADERS};
+
+    impl<S, F, D, E, R> Responder for GifStream<S, F>
+    where
+        S: Clone + Send + 'static,
+        F: Fn(S) -> R + 'static,
+        R: Future<Output = Result<D, E>> + Send + 'static,
+        D: AsRef<[u8]> + 'static,
+        E: fmt::Debug + fmt::Display + 'static,
+    {
+        type Body = BoxBody;
+
+        fn respond_to(self, _req: &HttpRequest) -> HttpResponse<Self::Body> {
+            let mut res = HttpResponse::Ok();
+            for header in GIF_HEADERS {
+                res.append_header(header);
+            }
+
+            res.streaming(self.stream().map(|chunk| chunk.map(Bytes::from)))
+        }
+    }
+}
+
+#[cfg(feature = "warp")]
+mod warp_reply {
+    use std::fmt;
+
+    use futures::Future;
+    use warp::{http::Response, hyper::Body, reply, Reply};
+
+    use crate::{GifStream, GIF_HEADERS};
+
+    impl<S, F, D, E, R> Reply for GifStream<S, F>
+    where
+        S: Clone + Send + 'static,
+        F: Fn(S) -> R + Send + 'static,
+        R: Future<Output = Result<D, E>> + Send + 'static,
+        D: AsRef<[u8]> + Send + 'static,
+        E: fmt::Debug + fmt::Display + Send + Sync + 'static,
+    {
+        fn into_response(self) -> reply::Response {
+            let mut res = Response::builder();
+            for (name, value) in GIF_HEADERS {
+                res = res.header(name, value);
+            }
+
+            // GIF_HEADERS only holds valid header names and values
+            res.body(Body::wrap_stream(self.stream())).unwrap()
+        }
+    }
+}
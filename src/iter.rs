@@ -0,0 +1,85 @@
+use crate::{Delays, FrameEncoder, GifEncoder, GifError, GifStream, PaletteCache};
+
+// Synchronous counterpart of GifStream::stream, for when there is no runtime
+// or no need for timing, e.g. assembling frames read from files
+// the generator is called once per frame, and the gif ends (with the trailer)
+// when it returns None or the take_frames budget runs out
+pub struct GifIter<S, F> {
+    gs: GifStream<S, F>,
+    cache: PaletteCache,
+    encoder: FrameEncoder,
+    delays: Delays,
+    sent: Option<usize>,
+    done: bool,
+}
+
+impl<S, F, D> GifStream<S, F>
+where
+    S: Clone,
+    F: Fn(S) -> Option<D>,
+    D: AsRef<[u8]>,
+{
+    // the interval is only used as the displayed frame delay
+    pub fn iter(self) -> GifIter<S, F> {
+        GifIter {
+            encoder: self.encoder(),
+            delays: self.delays(),
+            gs: self,
+            cache: PaletteCache::default(),
+            sent: None,
+            done: false,
+        }
+    }
+}
+
+impl<S, F, D> Iterator for GifIter<S, F>
+where
+    S: Clone,
+    F: Fn(S) -> Option<D>,
+    D: AsRef<[u8]>,
+{
+    type Item = Result<Vec<u8>, GifError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let gs = &self.gs;
+        let Some(sent) = self.sent else {
+            self.sent = Some(0);
+            return Some(Ok(gs.header_bytes(&[])));
+        };
+
+        let data = match gs.frames {
+            Some(n) if sent >= n => None,
+            _ => (gs.generator)(gs.state.clone()),
+        };
+
+        let Some(data) = data else {
+            self.done = true;
+
+            let mut buf = Vec::new();
+            GifEncoder::write_trailer(&mut buf);
+            return Some(Ok(buf));
+        };
+
+        let mut buf = Vec::new();
+        let frame = gs.write_local_frame(
+            &mut buf,
+            data.as_ref(),
+            self.delays.next(),
+            &mut self.cache,
+            &mut self.encoder,
+            &mut |_| {},
+        );
+
+        if let Err(e) = frame {
+            self.done = true;
+            return Some(Err(e));
+        }
+
+        self.sent = Some(sent + 1);
+        Some(Ok(buf))
+    }
+}
@@ -1,37 +1,108 @@
+mod builder;
+pub mod error;
 pub mod gif;
+#[cfg(any(feature = "axum", feature = "actix", feature = "warp"))]
+mod http;
+mod iter;
+mod neuquant;
+pub mod quantize;
+pub mod scale;
+#[cfg(feature = "tokio")]
+mod stream;
 use std::time::Duration;
 
-use async_stream::try_stream;
-use futures::{Future, Stream};
+pub use builder::GifBuilder;
+pub use error::*;
 pub use gif::*;
-
-#[derive(Clone, Copy)]
+pub use iter::*;
+pub use quantize::*;
+pub use scale::ResizeFilter;
+#[cfg(all(feature = "tokio", feature = "bytes"))]
+pub use stream::bytes_stream;
+#[cfg(feature = "tokio")]
+pub use stream::{inspect_frames, GifReader};
+
+#[derive(Clone)]
 pub struct GifStream<S, F> {
+    // only the async streams generate frames on an interval
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
     interval: Duration,
+    #[cfg(feature = "tokio")]
+    missed_tick: tokio::time::MissedTickBehavior,
     frame_delay: u16,
+    min_delay: u16,
     width: u16,
     height: u16,
 
     speed: i32,
     pub interlaced: bool,
     pub dispose: DisposalMethod,
+    pub dither: bool,
+    dither_amount: f32,
+    palette_refresh: usize,
+    quantizer: Quantizer,
+    repeat: Option<Repeat>,
+    frames: Option<usize>,
+    background: u8,
+    aspect_ratio: u8,
+    relearn_every: usize,
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    max_consecutive_errors: usize,
+    version: GifVersion,
+    min_code_size: Option<u8>,
+    trim_transparent: bool,
+    alternate_delays: bool,
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    coalesce: usize,
+    normalize_alpha: bool,
+    dedupe_identical: bool,
+    // the application extensions, already encoded
+    app_extensions: Vec<u8>,
 
     pub state: S,
     generator: F,
 }
 
-pub const GIF_HEADERS: [(&'static str, &'static str); 8] = [
+// one combined Cache-Control, some clients only look at the first of several
+pub const GIF_HEADERS: [(&str, &str); 6] = [
     ("Content-Type", "image/gif"),
     ("Content-Transfer-Encoding", "binary"),
-    ("Cache-Control", "no-cache"),
-    ("Cache-Control", "no-store"),
-    ("Cache-Control", "no-transform"),
+    ("Cache-Control", "no-cache, no-store, no-transform"),
     ("Expires", "0"),
     // cors
     ("Access-Control-Allow-Origin", "*"),
     ("Access-Control-Allow-Methods", "GET"),
 ];
 
+// How a gif response may be cached, see gif_headers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CachePolicy {
+    // never cached, for live streams (the GIF_HEADERS defaults)
+    #[default]
+    Live,
+    // cacheable by browsers and CDNs for max_age seconds, for gifs that don't change
+    Cacheable {
+        max_age: u32,
+    },
+}
+
+// GIF_HEADERS with the caching headers for the given policy
+// content type and cors are the same for every policy
+pub fn gif_headers(cache: CachePolicy) -> Vec<(&'static str, String)> {
+    let mut headers = Vec::with_capacity(GIF_HEADERS.len());
+    for (name, value) in GIF_HEADERS {
+        match (cache, name) {
+            (CachePolicy::Cacheable { .. }, "Expires") => {}
+            (CachePolicy::Cacheable { max_age }, "Cache-Control") => {
+                headers.push((name, format!("public, max-age={}", max_age)))
+            }
+            _ => headers.push((name, value.to_string())),
+        }
+    }
+
+    headers
+}
+
 pub const MIN_DELAY: u128 = 10; // in ms
 pub const MAX_DELAY: u128 = 65535; // in 100ths of a second
 
@@ -46,145 +117,474 @@ impl<S, F> GifStream<S, F> {
         self
     }
 
+    // dispose is the disposal method written with every frame, defaults to Any
+    // use Keep when frames are drawn over the previous one, e.g. with transparency
+    // the diffed streams ignore it and always use Keep, see DisposalMethod::Previous
     pub fn dispose(mut self, dispose: DisposalMethod) -> Self {
         self.dispose = dispose;
         self
     }
 
+    // dither enables floyd-steinberg dithering for streams without a global palette
+    pub fn dither(mut self, dither: bool) -> Self {
+        self.dither = dither;
+        self
+    }
+
+    // dither_amount scales how much of the quantization error is diffused when
+    // dithering, from 0.0 (plain nearest color) to 1.0 (the default), clamped
+    pub fn dither_amount(mut self, amount: f32) -> Self {
+        self.dither_amount = if amount.is_nan() {
+            0.0
+        } else {
+            amount.clamp(0.0, 1.0)
+        };
+        self
+    }
+
     // speed is the speed of the color quantization algorithm
     // speed must be between 1 and 30
     // 1 produces the nicest looking gif (but is slow)
     // 10 is a good balance between quality and speed
     // 30 produces a poor quality gif (but is fast)
-    pub fn speed(mut self, speed: i32) -> Self {
-        assert!(speed > 0 && speed <= 30, "speed must be between 1 and 30");
+    // it is the sample factor used whenever the stream learns a palette,
+    // streams mapping onto a fixed GlobalPalette don't depend on it per frame
+    pub fn speed(self, speed: i32) -> Self {
+        match self.try_speed(speed) {
+            Ok(s) => s,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    // same as speed, but returns an error instead of panicking
+    pub fn try_speed(mut self, speed: i32) -> Result<Self, GifError> {
+        if !(1..=30).contains(&speed) {
+            return Err(GifError::InvalidSpeed(speed));
+        }
+
         self.speed = speed;
+        Ok(self)
+    }
+
+    // quantizer is the algorithm used to learn palettes, defaults to NeuQuant
+    pub fn quantizer(mut self, quantizer: Quantizer) -> Self {
+        self.quantizer = quantizer;
         self
     }
-}
 
-impl<S, F, D, E, R> GifStream<S, F>
-where
-    S: Clone + Send,
-    F: Fn(S) -> R,
-    R: Future<Output = Result<D, E>> + Send + 'static,
-    D: AsRef<[u8]>,
-{
-    pub fn new(interval: Duration, width: u16, height: u16, state: S, image_generator: F) -> Self {
-        let delay = interval.as_millis().max(MIN_DELAY);
-        let frame_delay = (delay / 10).min(MAX_DELAY) as u16;
+    // palette_refresh controls how often streams without a global palette
+    // relearn their local palette, every n frames (defaults to every frame)
+    // in between, the last learned palette is reused as the local color table
+    // 0 learns the palette once and reuses it for the rest of the stream
+    pub fn palette_refresh(mut self, every: usize) -> Self {
+        self.palette_refresh = every;
+        self
+    }
+
+    // lock_palette_after_first learns the local palette from the first frame and
+    // reuses it for every frame after, so slowly changing content doesn't shimmer
+    // shorthand for palette_refresh(0), false goes back to relearning every frame
+    pub fn lock_palette_after_first(self, lock: bool) -> Self {
+        self.palette_refresh(if lock { 0 } else { 1 })
+    }
+
+    // background is the global palette index the screen is cleared to
+    // by DisposalMethod::Background, defaults to 0
+    pub fn background(mut self, index: u8) -> Self {
+        self.background = index;
+        self
+    }
+
+    // aspect_ratio is the raw pixel aspect ratio byte of the screen descriptor
+    // n means an aspect of (n + 15) / 64, defaults to 0 (no information)
+    pub fn aspect_ratio(mut self, aspect: u8) -> Self {
+        self.aspect_ratio = aspect;
+        self
+    }
+
+    // version selects the GIF87a signature for old decoders, defaults to GIF89a
+    // GIF87a streams have no looping, delay, disposal or transparency
+    pub fn version(mut self, version: GifVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    // min_code_size fixes the lzw minimum code size of every frame (2 to 8)
+    // instead of the smallest one that fits, see try_lzw_encode_with_code_size
+    // frames with indices that don't fit error
+    pub fn min_code_size(mut self, size: u8) -> Self {
+        self.min_code_size = Some(size);
+        self
+    }
+
+    // trim_transparent crops frames without a global palette to the bounding box
+    // of their visible pixels, see Frame::trim_transparent. it shrinks frames with
+    // large transparent margins, pick the disposal for what's left outside of them
+    pub fn trim_transparent(mut self, trim: bool) -> Self {
+        self.trim_transparent = trim;
+        self
+    }
+
+    // the delay every frame is displayed for, gifs count delays in 100ths of a second
+    // so the interval is rounded down to that, e.g. 33ms (30fps) shows for 30ms
+    pub fn effective_delay(&self) -> Duration {
+        Duration::from_millis(self.frame_delay as u64 * 10)
+    }
+
+    // min_delay is the floor every frame delay is clamped to, defaults to MIN_DELAY
+    // 10ms is also the smallest delay the format can represent (1/100s), so the
+    // floor can only be raised, e.g. to the 20ms below which browsers slow gifs down
+    // the floor is rounded down to 100ths of a second, longer delays are unaffected
+    pub fn min_delay(self, floor: Duration) -> Self {
+        match self.try_min_delay(floor) {
+            Ok(s) => s,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    // same as min_delay, but returns an error instead of panicking
+    pub fn try_min_delay(mut self, floor: Duration) -> Result<Self, GifError> {
+        if floor.as_millis() < MIN_DELAY {
+            return Err(GifError::InvalidDelay(floor));
+        }
+
+        self.min_delay = (floor.as_millis() / 10).min(MAX_DELAY) as u16;
+        self.frame_delay = frame_delay(self.interval).max(self.min_delay);
+        Ok(self)
+    }
+
+    // alternate_delays varies the delay of each frame between the centiseconds
+    // around the interval, so they add up to the interval on average instead of
+    // drifting, e.g. 3, 4, 3 for 33ms. defaults to false (always effective_delay)
+    pub fn alternate_delays(mut self, alternate: bool) -> Self {
+        self.alternate_delays = alternate;
+        self
+    }
+
+    // coalesce makes the async streams yield n encoded frames per chunk instead of
+    // one, for fewer writes downstream at high frame rates. the header is still
+    // the first chunk on its own and a bounded stream's trailer goes out with its
+    // last, possibly partial, chunk. defaults to 1
+    // anything downstream sees chunks rather than frames, inspect_frames included
+    pub fn coalesce(mut self, n: usize) -> Self {
+        self.coalesce = n.max(1);
+        self
+    }
+
+    // normalize_alpha hard clips the alpha of every frame without a global palette
+    // before quantizing, see normalize_alpha, so semi transparent edges are learned
+    // as the opaque colors they're drawn as. defaults to false
+    pub fn normalize_alpha(mut self, normalize: bool) -> Self {
+        self.normalize_alpha = normalize;
+        self
+    }
+
+    // dedupe_identical re-yields the last frame of stream (and the streams built
+    // on it), stream_inspect, stream_ticked, stream_adjustable, stream_until,
+    // stream_timed, stream_sparse, stream_received and iter when the generator
+    // returns exactly the same data again, instead of learning a palette and
+    // compressing it all over. only the delay is rewritten
+    // it keeps a copy of the last frame's data and bytes to compare against
+    pub fn dedupe_identical(mut self, dedupe: bool) -> Self {
+        self.dedupe_identical = dedupe;
+        self
+    }
+
+    // repeat emits a looping extension at the start of the stream
+    // by default no extension is written and looping is left to the viewer
+    pub fn repeat(mut self, repeat: Repeat) -> Self {
+        self.repeat = Some(repeat);
+        self
+    }
+
+    // application_extension writes an application extension right after the header
+    // e.g. to identify the tool that made the gif, extensions are written in the
+    // order they were added, after the looping extension and only for GIF89a
+    pub fn application_extension(
+        mut self,
+        identifier: [u8; 8],
+        auth_code: [u8; 3],
+        data: &[u8],
+    ) -> Self {
+        let extension = ExtensionData::Application {
+            identifier,
+            auth_code,
+            data: data.to_vec(),
+        };
+        GifEncoder::write_extension(&mut self.app_extensions, extension);
+        self
+    }
+
+    // take_frames ends every stream variant after `frames` frames, followed by the trailer
+    // by default streams run forever
+    pub fn take_frames(mut self, frames: usize) -> Self {
+        self.frames = Some(frames);
+        self
+    }
+
+    // the first chunk every stream starts with: the screen descriptor, the global
+    // color table, the looping extension if one was requested and the application
+    // extensions, for assembling a gif by hand with the same layout as the streams
+    // palette is the global color table (empty for none)
+    pub fn header_bytes(&self, palette: &[u8]) -> Vec<u8> {
+        let mut builder = GifBuilder::new(self.width, self.height)
+            .global_palette(palette)
+            .background(self.background)
+            .aspect_ratio(self.aspect_ratio)
+            .version(self.version);
+
+        if let Some(repeat) = self.repeat {
+            builder = builder.repeat(repeat);
+        }
+
+        builder.encoded_extensions(&self.app_extensions).header()
+    }
+
+    fn with_defaults(interval: Duration, width: u16, height: u16, state: S, generator: F) -> Self {
+        let frame_delay = frame_delay(interval);
 
         Self {
             interval,
+            #[cfg(feature = "tokio")]
+            missed_tick: tokio::time::MissedTickBehavior::Burst,
             frame_delay,
+            min_delay: (MIN_DELAY / 10) as u16,
             width,
             height,
 
             state,
-            generator: image_generator,
+            generator,
 
             speed: 10,
             interlaced: false,
-            dispose: DisposalMethod::Keep,
+            dispose: DisposalMethod::Any,
+            dither: false,
+            dither_amount: 1.0,
+            palette_refresh: 1,
+            quantizer: Quantizer::NeuQuant,
+            repeat: None,
+            frames: None,
+            background: 0,
+            aspect_ratio: 0,
+            relearn_every: 0,
+            max_consecutive_errors: 1,
+            version: GifVersion::Gif89a,
+            min_code_size: None,
+            trim_transparent: false,
+            alternate_delays: false,
+            coalesce: 1,
+            normalize_alpha: false,
+            dedupe_identical: false,
+            app_extensions: Vec::new(),
         }
     }
 
-    // default stream, assumes no global palette
-    // returns a stream of encoded gif frames
-    pub fn stream(self) -> impl Stream<Item = Result<Vec<u8>, E>> {
-        try_stream! {
-            let mut buf = Vec::new();
-            let flags = GifEncoder::global_palette_flags(&[]);
-            GifEncoder::write_screen_desc(&mut buf, self.width, self.height, Some(flags));
-            GifEncoder::write_color_table(&mut buf, &[]);
-            yield buf;
-
-            let mut interval = tokio::time::interval(self.interval);
-            loop {
-                interval.tick().await;
+    // relearn_every rebuilds the palette of stream_auto_palette every n frames
+    // so it follows content that changes over time, defaults to 0 (never)
+    pub fn relearn_every(mut self, every: usize) -> Self {
+        self.relearn_every = every;
+        self
+    }
 
-                let mut buf = Vec::new();
+    // max_consecutive_errors lets the async streams ride out a flaky generator
+    // an error is dropped and the generator retried on the next tick, the stream
+    // only ends with the error after n errors in a row, defaults to 1
+    // errors can be logged from the generator before returning them
+    pub fn max_consecutive_errors(mut self, n: usize) -> Self {
+        self.max_consecutive_errors = n.max(1);
+        self
+    }
 
-                let data =  (self.generator)(self.state.clone()).await?;
-                let frame = Frame::from_rgba(self.width, self.height, data.as_ref(), self.speed);
+    // quantizes a frame with a local palette, relearning it when due
+    // errors when data isn't exactly width * height rgba pixels
+    fn local_frame(&self, data: &[u8], cache: &mut PaletteCache) -> Result<Frame, GifError> {
+        check_buffer_len(self.width, self.height, 4, data)?;
+
+        let normalized;
+        let data = if self.normalize_alpha {
+            let mut copy = data.to_vec();
+            normalize_alpha(&mut copy);
+            normalized = copy;
+            &normalized
+        } else {
+            data
+        };
+
+        let due = match self.palette_refresh {
+            0 => false,
+            n => cache.age.is_multiple_of(n),
+        };
+
+        let lp = match cache.palette.take() {
+            Some(lp) if !due && lp.covers(data) => lp,
+            _ => LocalPalette::new(self.quantizer, self.speed, data, 1),
+        };
+
+        let mut frame = self.palette_frame(data, &lp);
+        if self.trim_transparent {
+            frame.trim_transparent();
+        }
 
-                GifEncoder::write_frame(
-                    &mut buf,
-                    &frame,
-                    self.frame_delay,
-                    self.interlaced,
-                    self.dispose,
-                );
+        cache.palette = Some(lp);
+        cache.age += 1;
 
-                yield buf;
-            }
-        }
+        Ok(frame)
     }
 
-    // stream with global palette
-    // returns a stream of encoded gif frames
-    pub fn stream_with_palette(self, gp: GlobalPalette) -> impl Stream<Item = Result<Vec<u8>, E>> {
-        try_stream! {
-            let mut buf = Vec::new();
-            let flags = GifEncoder::global_palette_flags(gp.palette());
-            GifEncoder::write_screen_desc(&mut buf, self.width, self.height, Some(flags));
-            GifEncoder::write_color_table(&mut buf, gp.palette());
-            yield buf;
+    // writes local_frame with the encoder, or the last frame again with its delay
+    // rewritten if dedupe_identical is on and data is the same as last time
+    // on_frame sees the frame either way
+    fn write_local_frame(
+        &self,
+        buf: &mut Vec<u8>,
+        data: &[u8],
+        delay: u16,
+        cache: &mut PaletteCache,
+        encoder: &mut FrameEncoder,
+        on_frame: &mut impl FnMut(&Frame),
+    ) -> Result<(), GifError> {
+        let start = buf.len();
+
+        if let Some(last) = cache.last.as_ref().filter(|last| last.data == data) {
+            on_frame(&last.frame);
+            buf.extend_from_slice(&last.encoded);
+            set_frame_delay(&mut buf[start..], delay);
+            return Ok(());
+        }
 
-            let mut interval = tokio::time::interval(self.interval);
-            loop {
-                interval.tick().await;
+        let frame = self.local_frame(data, cache)?;
+        on_frame(&frame);
+        encoder.try_write_frame(buf, &frame, delay, self.interlaced, self.dispose)?;
 
-                let mut buf = Vec::new();
+        if self.dedupe_identical {
+            cache.last = Some(LastFrame {
+                data: data.to_vec(),
+                frame,
+                encoded: buf[start..].to_vec(),
+            });
+        }
 
-                let data =  (self.generator)(self.state.clone()).await?;
-                let frame = Frame::with_global_palette_rgba(self.width, self.height, data.as_ref(), &gp);
+        Ok(())
+    }
 
-                GifEncoder::write_frame(
-                    &mut buf,
-                    &frame,
-                    self.frame_delay,
-                    self.interlaced,
-                    self.dispose,
-                );
+    // the delays every stream writes its frames with
+    fn delays(&self) -> Delays {
+        Delays {
+            interval: self.alternate_delays.then_some(self.interval),
+            fixed: self.frame_delay,
+            min: self.min_delay,
+            elapsed: Duration::ZERO,
+            written: 0,
+        }
+    }
 
-                yield buf;
-            }
+    // the encoder every stream writes its frames with
+    fn encoder(&self) -> FrameEncoder {
+        let encoder = FrameEncoder::with_version(self.version);
+        match self.min_code_size {
+            Some(size) => encoder.min_code_size(size),
+            None => encoder,
         }
     }
 
-    // stream with auto generated global palette, given a number of colors
-    pub fn stream_auto_palette(self, n_colors: usize) -> impl Stream<Item = Result<Vec<u8>, E>> {
-        try_stream! {
-            let mut buf = Vec::new();
+    // the encoder of the global palette streams, every frame gets the code size
+    // of the palette instead of the smallest one its indices fit, unless min_code_size is set
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    fn palette_encoder(&self, colors: usize) -> FrameEncoder {
+        let size = self
+            .min_code_size
+            .unwrap_or_else(|| min_code_size_for_palette(colors));
+        FrameEncoder::with_version(self.version).min_code_size(size)
+    }
 
-            let data = (self.generator)(self.state.clone()).await?;
-            let gp = GlobalPalette::new(self.speed, n_colors, data.as_ref());
+    // maps data onto a local palette, dithering if enabled
+    fn palette_frame(&self, data: &[u8], lp: &LocalPalette) -> Frame {
+        if self.dither {
+            Frame::with_local_palette_rgba_dithered(
+                self.width,
+                self.height,
+                data,
+                lp,
+                self.dither_amount,
+            )
+        } else {
+            Frame::with_local_palette_rgba(self.width, self.height, data, lp)
+        }
+    }
+}
 
-            let flags = GifEncoder::global_palette_flags(gp.palette());
-            GifEncoder::write_screen_desc(&mut buf, self.width, self.height, Some(flags));
-            GifEncoder::write_color_table(&mut buf, gp.palette());
-            yield buf;
+// the last learned local palette of a stream and how many frames it has seen
+#[derive(Default)]
+struct PaletteCache {
+    palette: Option<LocalPalette>,
+    age: usize,
+    last: Option<LastFrame>,
+}
 
-            let mut interval = tokio::time::interval(self.interval);
-            loop {
-                interval.tick().await;
+// the data of the last frame and what it was written as, see dedupe_identical
+struct LastFrame {
+    data: Vec<u8>,
+    frame: Frame,
+    encoded: Vec<u8>,
+}
 
-                let mut buf = Vec::new();
+// rewrites the delay of the graphic control extension an encoded frame starts with
+// GIF87a frames have none and are left as is
+fn set_frame_delay(frame: &mut [u8], delay: u16) {
+    if let [0x21, 0xF9, 4, _, lo, hi, ..] = frame {
+        [*lo, *hi] = delay.to_le_bytes();
+    }
+}
 
-                let data = (self.generator)(self.state.clone()).await?;
-                let frame = Frame::with_global_palette_rgba(self.width, self.height, data.as_ref(), &gp);
+// the delay of each frame in turn, the fixed delay or, when alternating, whatever
+// brings the total written so far closest to the time that should have passed
+struct Delays {
+    interval: Option<Duration>,
+    fixed: u16,
+    min: u16,
+    elapsed: Duration,
+    written: u128,
+}
 
-                GifEncoder::write_frame(
-                    &mut buf,
-                    &frame,
-                    self.frame_delay,
-                    self.interlaced,
-                    self.dispose,
-                );
+impl Delays {
+    fn next(&mut self) -> u16 {
+        let Some(interval) = self.interval else {
+            return self.fixed;
+        };
+
+        self.elapsed += interval;
+        let total = (self.elapsed.as_millis() + 5) / 10;
+        let delay = total
+            .saturating_sub(self.written)
+            .clamp(self.min as u128, MAX_DELAY);
+        self.written += delay;
+        delay as u16
+    }
 
-                yield buf;
-            }
+    // switches to the delays of a new interval from the next frame on
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    fn set_interval(&mut self, interval: Duration) {
+        self.fixed = frame_delay(interval).max(self.min);
+        if self.interval.is_some() {
+            self.interval = Some(interval);
         }
     }
 }
+
+// converts a duration into a gif frame delay (in 100ths of a second)
+// clamped between MIN_DELAY and MAX_DELAY
+fn frame_delay(duration: Duration) -> u16 {
+    let delay = duration.as_millis().max(MIN_DELAY);
+    (delay / 10).min(MAX_DELAY) as u16
+}
+
+// state only has to be Send for the async streams, GifIter takes any Clone state
+impl<S, F, R> GifStream<S, F>
+where
+    S: Clone,
+    F: Fn(S) -> R,
+{
+    pub fn new(interval: Duration, width: u16, height: u16, state: S, image_generator: F) -> Self {
+        Self::with_defaults(interval, width, height, state, image_generator)
+    }
+}
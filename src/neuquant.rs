@@ -0,0 +1,403 @@
+/*
+NeuQuant Neural-Net Quantization Algorithm by Anthony Dekker, 1994.
+See "Kohonen neural networks for optimal colour quantization"
+in "Network: Computation in Neural Systems" Vol. 5 (1994) pp 351-367.
+for a discussion of the algorithm.
+See also http://members.ozemail.com.au/~dekker/NEUQUANT.HTML
+
+Incorporated bugfixes and alpha channel handling from pngnq
+http://pngnq.sourceforge.net
+
+Ported from the color_quant crate, Copyright (c) 2014 The Piston Developers
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+NeuQuant Neural-Net Quantization Algorithm
+------------------------------------------
+
+Copyright (c) 1994 Anthony Dekker
+
+NEUQUANT Neural-Net quantization algorithm by Anthony Dekker, 1994.
+See "Kohonen neural networks for optimal colour quantization"
+in "Network: Computation in Neural Systems" Vol. 5 (1994) pp 351-367.
+for a discussion of the algorithm.
+See also  http://members.ozemail.com.au/~dekker/NEUQUANT.HTML
+
+Any party obtaining a copy of these files from the author, directly or
+indirectly, is granted, free of charge, a full and unrestricted irrevocable,
+world-wide, paid up, royalty-free, nonexclusive right and license to deal
+in this software and documentation files (the "Software"), including without
+limitation the rights to use, copy, modify, merge, publish, distribute, sublicense,
+and/or sell copies of the Software, and to permit persons who receive
+copies from any such party to do so, with the only requirement being
+that this copyright notice remain intact.
+*/
+
+// color_quant's NeuQuant only reads rgba, this one also learns from and looks up
+// rgb pixels (taken as opaque) so rgb frames don't need an rgba copy
+// it also keeps sampling small inputs until every learning cycle had
+// CYCLE_SAMPLES pixels, otherwise it gives the same palettes as color_quant
+
+const RADIUS_DEC: i32 = 30; // factor of 1/30 each cycle
+
+// a 2x2 frame is only 4 samples, too few for the network to move off its
+// initial gray ramp, so it is cycled through until there are enough
+const CYCLE_SAMPLES: usize = 100;
+
+const ALPHA_BIASSHIFT: i32 = 10; // alpha starts at 1
+const INIT_ALPHA: i32 = 1 << ALPHA_BIASSHIFT; // biased by 10 bits
+
+const GAMMA: f64 = 1024.0;
+const BETA: f64 = 1.0 / GAMMA;
+const BETAGAMMA: f64 = BETA * GAMMA;
+
+// four primes near 500, assume no image has a length so large
+// that it is divisible by all four primes
+const PRIMES: [usize; 4] = [499, 491, 478, 503];
+
+#[derive(Clone, Copy)]
+struct Quad<T> {
+    r: T,
+    g: T,
+    b: T,
+    a: T,
+}
+
+type Neuron = Quad<f64>;
+type Color = Quad<i32>;
+
+pub(crate) struct NeuQuant {
+    network: Vec<Neuron>,
+    colormap: Vec<Color>,
+    netindex: Vec<usize>,
+    // bias and freq arrays for learning
+    bias: Vec<f64>,
+    freq: Vec<f64>,
+    samplefac: i32,
+    netsize: usize,
+}
+
+// the rgba channels of a pixel, rgb pixels are opaque
+fn channels(pix: &[u8]) -> (u8, u8, u8, u8) {
+    (pix[0], pix[1], pix[2], pix.get(3).copied().unwrap_or(0xFF))
+}
+
+impl NeuQuant {
+    // trains a network of `colors` neurons on rgba data
+    pub(crate) fn new(samplefac: i32, colors: usize, pixels: &[u8]) -> Self {
+        Self::with_channels(samplefac, colors, pixels, 4)
+    }
+
+    // trains a network of `colors` neurons on rgb data
+    pub(crate) fn from_rgb(samplefac: i32, colors: usize, pixels: &[u8]) -> Self {
+        Self::with_channels(samplefac, colors, pixels, 3)
+    }
+
+    fn with_channels(samplefac: i32, colors: usize, pixels: &[u8], channels: usize) -> Self {
+        let netsize = colors;
+        let freq = (netsize as f64).recip();
+
+        let mut network = Vec::with_capacity(netsize);
+        for i in 0..netsize {
+            let tmp = (i as f64) * 256.0 / (netsize as f64);
+            // alpha starts at 0 for the dark neurons
+            let a = if i < 16 { i as f64 * 16.0 } else { 255.0 };
+            network.push(Neuron {
+                r: tmp,
+                g: tmp,
+                b: tmp,
+                a,
+            });
+        }
+
+        let mut nq = NeuQuant {
+            network,
+            colormap: vec![
+                Color {
+                    r: 0,
+                    g: 0,
+                    b: 0,
+                    a: 255
+                };
+                netsize
+            ],
+            netindex: vec![0; 256],
+            bias: vec![0.0; netsize],
+            freq: vec![freq; netsize],
+            samplefac,
+            netsize,
+        };
+        nq.learn(pixels, channels);
+        nq.build_colormap();
+        nq.build_netindex();
+        nq
+    }
+
+    // the best matching index in the color map for an rgba or rgb pixel
+    pub(crate) fn index_of(&self, pix: &[u8]) -> usize {
+        let (r, g, b, a) = channels(pix);
+        self.search_netindex(b, g, r, a)
+    }
+
+    pub(crate) fn color_map_rgb(&self) -> Vec<u8> {
+        self.colormap
+            .iter()
+            .flat_map(|c| [c.r as u8, c.g as u8, c.b as u8])
+            .collect()
+    }
+
+    // move neuron i towards biased (a,b,g,r) by factor alpha
+    fn salter_single(&mut self, alpha: f64, i: i32, quad: Quad<f64>) {
+        let n = &mut self.network[i as usize];
+        n.b -= alpha * (n.b - quad.b);
+        n.g -= alpha * (n.g - quad.g);
+        n.r -= alpha * (n.r - quad.r);
+        n.a -= alpha * (n.a - quad.a);
+    }
+
+    // move the neurons next to i towards biased (a,b,g,r) by factor alpha
+    fn alter_neighbour(&mut self, alpha: f64, rad: i32, i: i32, quad: Quad<f64>) {
+        let lo = (i - rad).max(0);
+        let hi = (i + rad).min(self.netsize as i32);
+        let mut j = i + 1;
+        let mut k = i - 1;
+        let mut q = 0;
+
+        while (j < hi) || (k > lo) {
+            let rad_sq = rad as f64 * rad as f64;
+            let alpha = (alpha * (rad_sq - q as f64 * q as f64)) / rad_sq;
+            q += 1;
+            if j < hi {
+                let p = &mut self.network[j as usize];
+                p.b -= alpha * (p.b - quad.b);
+                p.g -= alpha * (p.g - quad.g);
+                p.r -= alpha * (p.r - quad.r);
+                p.a -= alpha * (p.a - quad.a);
+                j += 1;
+            }
+            if k > lo {
+                let p = &mut self.network[k as usize];
+                p.b -= alpha * (p.b - quad.b);
+                p.g -= alpha * (p.g - quad.g);
+                p.r -= alpha * (p.r - quad.r);
+                p.a -= alpha * (p.a - quad.a);
+                k -= 1;
+            }
+        }
+    }
+
+    // finds the closest neuron (min dist) and updates freq
+    // finds the best neuron (min dist-bias) and returns its position
+    // for frequently chosen neurons freq[i] is high and bias[i] is negative
+    // bias[i] = gamma*((1/self.netsize)-freq[i])
+    fn contest(&mut self, b: f64, g: f64, r: f64, a: f64) -> i32 {
+        let mut bestd = f64::MAX;
+        let mut bestbiasd = bestd;
+        let mut bestpos = -1;
+        let mut bestbiaspos = bestpos;
+
+        for i in 0..self.netsize {
+            let bestbiasd_biased = bestbiasd + self.bias[i];
+            let n = &self.network[i];
+            let mut dist = (n.b - b).abs();
+            dist += (n.r - r).abs();
+            if dist < bestd || dist < bestbiasd_biased {
+                dist += (n.g - g).abs();
+                dist += (n.a - a).abs();
+                if dist < bestd {
+                    bestd = dist;
+                    bestpos = i as i32;
+                }
+                let biasdist = dist - self.bias[i];
+                if biasdist < bestbiasd {
+                    bestbiasd = biasdist;
+                    bestbiaspos = i as i32;
+                }
+            }
+            self.freq[i] -= BETA * self.freq[i];
+            self.bias[i] += BETAGAMMA * self.freq[i];
+        }
+        self.freq[bestpos as usize] += BETA;
+        self.bias[bestpos as usize] -= BETAGAMMA;
+        bestbiaspos
+    }
+
+    // main learning loop, pixels are `channels` bytes apart
+    // the number of learning cycles is crucial and the parameters are not
+    // optimized for net sizes < 26 or > 256
+    fn learn(&mut self, pixels: &[u8], channels: usize) {
+        // for 256 colors the radius starts at 32
+        let initrad = self.netsize as i32 / 8;
+        let radiusbiasshift = 6;
+        let radiusbias = 1 << radiusbiasshift;
+        let mut bias_radius = initrad * radiusbias;
+        let alphadec = 30 + ((self.samplefac - 1) / 3);
+        let lengthcount = pixels.len() / channels;
+        if lengthcount == 0 {
+            return;
+        }
+        // learning cycles
+        let n_cycles = (self.netsize >> 1).max(100);
+        let samplepixels = (lengthcount / self.samplefac as usize).max(n_cycles * CYCLE_SAMPLES);
+        let delta = (samplepixels / n_cycles).max(1);
+        let mut alpha = INIT_ALPHA;
+
+        let mut rad = bias_radius >> radiusbiasshift;
+        if rad <= 1 {
+            rad = 0
+        };
+
+        let mut pos = 0;
+        let step = *PRIMES
+            .iter()
+            .find(|&&prime| !lengthcount.is_multiple_of(prime))
+            .unwrap_or(&PRIMES[3]);
+
+        let mut i = 0;
+        while i < samplepixels {
+            let (r, g, b, a) = channels_f64(&pixels[channels * pos..][..channels]);
+
+            let j = self.contest(b, g, r, a);
+
+            let alpha_ = alpha as f64 / INIT_ALPHA as f64;
+            self.salter_single(alpha_, j, Quad { b, g, r, a });
+            if rad > 0 {
+                self.alter_neighbour(alpha_, rad, j, Quad { b, g, r, a })
+            };
+
+            pos += step;
+            while pos >= lengthcount {
+                pos -= lengthcount
+            }
+
+            i += 1;
+            if i.is_multiple_of(delta) {
+                alpha -= alpha / alphadec;
+                bias_radius -= bias_radius / RADIUS_DEC;
+                rad = bias_radius >> radiusbiasshift;
+                if rad <= 1 {
+                    rad = 0
+                };
+            }
+        }
+    }
+
+    fn build_colormap(&mut self) {
+        for (color, n) in self.colormap.iter_mut().zip(&self.network) {
+            color.b = (n.b.round() as i32).clamp(0, 255);
+            color.g = (n.g.round() as i32).clamp(0, 255);
+            color.r = (n.r.round() as i32).clamp(0, 255);
+            color.a = (n.a.round() as i32).clamp(0, 255);
+        }
+    }
+
+    // insertion sort of the color map on g, and building of netindex[0..255]
+    fn build_netindex(&mut self) {
+        let mut previouscol = 0;
+        let mut startpos = 0;
+
+        for i in 0..self.netsize {
+            // find the smallest g in i..netsize
+            let mut smallpos = i;
+            let mut smallval = self.colormap[i].g as usize;
+            for j in (i + 1)..self.netsize {
+                if (self.colormap[j].g as usize) < smallval {
+                    smallpos = j;
+                    smallval = self.colormap[j].g as usize;
+                }
+            }
+            self.colormap.swap(i, smallpos);
+
+            // the smallval entry is now in position i
+            if smallval != previouscol {
+                self.netindex[previouscol] = (startpos + i) >> 1;
+                for j in (previouscol + 1)..smallval {
+                    self.netindex[j] = i
+                }
+                previouscol = smallval;
+                startpos = i;
+            }
+        }
+        let max_netpos = self.netsize - 1;
+        self.netindex[previouscol] = (startpos + max_netpos) >> 1;
+        for j in (previouscol + 1)..256 {
+            self.netindex[j] = max_netpos
+        }
+    }
+
+    // distance of color map entry i from the pixel, or None once the g
+    // distance alone is past bestd and the search can stop
+    fn entry_dist(&self, i: usize, b: u8, g: u8, r: u8, a: u8, bestd: i32) -> Option<i32> {
+        let p = self.colormap[i];
+        let mut e = p.g - g as i32;
+        let mut dist = e * e;
+        if dist >= bestd {
+            return None;
+        }
+        e = p.b - b as i32;
+        dist += e * e;
+        if dist < bestd {
+            e = p.r - r as i32;
+            dist += e * e;
+            if dist < bestd {
+                e = p.a - a as i32;
+                dist += e * e;
+            }
+        }
+        Some(dist)
+    }
+
+    // search for the best matching color, starting at netindex[g]
+    // and working outwards
+    fn search_netindex(&self, b: u8, g: u8, r: u8, a: u8) -> usize {
+        let mut bestd = 1 << 30;
+        let mut best = 0;
+        let mut i = self.netindex[g as usize];
+        let mut j = i.saturating_sub(1);
+
+        while (i < self.netsize) || (j > 0) {
+            if i < self.netsize {
+                let Some(dist) = self.entry_dist(i, b, g, r, a, bestd) else {
+                    break;
+                };
+                if dist < bestd {
+                    bestd = dist;
+                    best = i;
+                }
+                i += 1;
+            }
+            if j > 0 {
+                let Some(dist) = self.entry_dist(j, b, g, r, a, bestd) else {
+                    break;
+                };
+                if dist < bestd {
+                    bestd = dist;
+                    best = j;
+                }
+                j -= 1;
+            }
+        }
+        best
+    }
+}
+
+fn channels_f64(pix: &[u8]) -> (f64, f64, f64, f64) {
+    let (r, g, b, a) = channels(pix);
+    (r as f64, g as f64, b as f64, a as f64)
+}
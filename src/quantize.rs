@@ -0,0 +1,375 @@
+use crate::neuquant::NeuQuant;
+use crate::GifError;
+
+// The algorithm used to learn a palette
+// NeuQuant gives the best looking palettes, MedianCut is deterministic,
+// so similar frames get the same palette and colors don't drift between them
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Quantizer {
+    #[default]
+    NeuQuant,
+    MedianCut,
+}
+
+// How a palette is learned, see GlobalPalette::with_options and
+// Frame::from_rgba_with_options
+// sample_factor is the quantizer's own setting and has nothing to do with a
+// stream's speed, a palette learned at 1 maps pixels as fast as one learned at 30
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PaletteOptions {
+    pub(crate) quantizer: Quantizer,
+    pub(crate) sample_factor: i32,
+    pub(crate) colors: usize,
+}
+
+impl Default for PaletteOptions {
+    fn default() -> Self {
+        Self {
+            quantizer: Quantizer::NeuQuant,
+            sample_factor: 10,
+            colors: 256,
+        }
+    }
+}
+
+impl PaletteOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn quantizer(mut self, quantizer: Quantizer) -> Self {
+        self.quantizer = quantizer;
+        self
+    }
+
+    // the palette is learned from one in every `sample_factor` pixels (1 to 30),
+    // 1 is the slowest and most accurate, defaults to 10
+    pub fn sample_factor(self, sample_factor: i32) -> Self {
+        match self.try_sample_factor(sample_factor) {
+            Ok(o) => o,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn try_sample_factor(mut self, sample_factor: i32) -> Result<Self, GifError> {
+        if !(1..=30).contains(&sample_factor) {
+            return Err(GifError::InvalidSpeed(sample_factor));
+        }
+
+        self.sample_factor = sample_factor;
+        Ok(self)
+    }
+
+    // at most this many colors are learned (1 to 256), defaults to 256
+    pub fn colors(self, colors: usize) -> Self {
+        match self.try_colors(colors) {
+            Ok(o) => o,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn try_colors(mut self, colors: usize) -> Result<Self, GifError> {
+        if !(1..=256).contains(&colors) {
+            return Err(GifError::InvalidColorCount(colors));
+        }
+
+        self.colors = colors;
+        Ok(self)
+    }
+}
+
+// A learned palette and the lookup from pixels onto it, the extension point for
+// quantizers beyond the built in ones, see Frame::from_rgba_quantized
+// palette is packed rgb with at most 256 colors, index_of gets an rgba pixel
+pub trait Quantize {
+    fn palette(&self) -> &[u8];
+    fn index_of(&self, pix: &[u8]) -> u8;
+}
+
+// NeuQuant as a Quantize, for mixing it with custom quantizers
+pub struct NeuQuantizer {
+    nq: NeuQuant,
+    palette: Vec<u8>,
+}
+
+impl NeuQuantizer {
+    // learns up to `colors` colors from rgba data, speed is the sample factor (1 to 30)
+    pub fn new(speed: i32, colors: usize, data: &[u8]) -> Self {
+        let nq = neuquant(speed, colors, data);
+        Self {
+            palette: nq.color_map_rgb(),
+            nq,
+        }
+    }
+}
+
+impl Quantize for NeuQuantizer {
+    fn palette(&self) -> &[u8] {
+        &self.palette
+    }
+
+    fn index_of(&self, pix: &[u8]) -> u8 {
+        self.nq.index_of(pix) as u8
+    }
+}
+
+// A palette learned by one of the quantizers
+pub(crate) enum Quantized {
+    NeuQuant(NeuQuant),
+    MedianCut(MedianCut),
+    WebSafe,
+}
+
+impl Quantized {
+    pub(crate) fn new(quantizer: Quantizer, speed: i32, colors: usize, data: &[u8]) -> Self {
+        match quantizer {
+            Quantizer::NeuQuant => Quantized::NeuQuant(neuquant(speed, colors, data)),
+            Quantizer::MedianCut => Quantized::MedianCut(MedianCut::new(speed, colors, data)),
+        }
+    }
+
+    pub(crate) fn color_map_rgb(&self) -> Vec<u8> {
+        match self {
+            Quantized::NeuQuant(nq) => nq.color_map_rgb(),
+            Quantized::MedianCut(mc) => mc.palette().to_vec(),
+            Quantized::WebSafe => web_safe_palette(),
+        }
+    }
+
+    pub(crate) fn index_of(&self, pix: &[u8]) -> u8 {
+        match self {
+            Quantized::NeuQuant(nq) => nq.index_of(pix) as u8,
+            Quantized::MedianCut(mc) => mc.index_of(pix),
+            Quantized::WebSafe => web_safe_index_of(pix),
+        }
+    }
+}
+
+// NeuQuant::new with the sample factor and color count clamped to what the data can give
+// it learns from one in every `speed` pixels, so a favicon sized frame at speed 10
+// would skip most of its pixels, small frames are sampled at a lower speed
+// empty data gets a single black pixel to learn from
+pub(crate) fn neuquant(speed: i32, colors: usize, data: &[u8]) -> NeuQuant {
+    const BLACK: [u8; 4] = [0, 0, 0, 0xFF];
+    let data = if data.len() < 4 { &BLACK[..] } else { data };
+
+    let speed = sample_speed(speed, data.len() / 4);
+    NeuQuant::new(speed, colors.clamp(1, 256), data)
+}
+
+// same as neuquant, for tightly packed rgb data
+pub(crate) fn neuquant_rgb(speed: i32, colors: usize, data: &[u8]) -> NeuQuant {
+    const BLACK: [u8; 3] = [0, 0, 0];
+    let data = if data.len() < 3 { &BLACK[..] } else { data };
+
+    let speed = sample_speed(speed, data.len() / 3);
+    NeuQuant::from_rgb(speed, colors.clamp(1, 256), data)
+}
+
+// the sample factor clamped to keep at least 100 samples, or every pixel of smaller data
+fn sample_speed(speed: i32, pixels: usize) -> i32 {
+    speed.clamp(1, (pixels / 100).clamp(1, 30) as i32)
+}
+
+// Median cut quantizer
+// colors are bucketed at 5 bits per channel, then the bucket with the widest
+// channel range is repeatedly split at its median until there are enough boxes
+// each palette entry is the average color of a box
+// lookups go through a table of the nearest entry for every bucket seen while
+// learning, colors in other buckets fall back to searching the palette
+pub struct MedianCut {
+    palette: Vec<u8>,
+    lookup: Vec<u16>,
+}
+
+// lookup entry of a bucket with no precomputed nearest color
+const NOT_CACHED: u16 = u16::MAX;
+
+#[derive(Clone, Copy)]
+struct Bucket {
+    key: usize,
+    count: u64,
+    sum: [u64; 3],
+}
+
+impl Bucket {
+    fn channel(&self, c: usize) -> usize {
+        (self.key >> (10 - c * 5)) & 0x1F
+    }
+}
+
+fn bucket_key(pix: &[u8]) -> usize {
+    (pix[0] as usize >> 3) << 10 | (pix[1] as usize >> 3) << 5 | pix[2] as usize >> 3
+}
+
+impl MedianCut {
+    // learns up to `colors` colors from rgba data, ignoring fully transparent pixels
+    // speed (1 to 30) is the sampling stride over the opaque pixels, 1 looks at every
+    // one, and frames with only a few hundred of them are always fully sampled
+    pub fn new(speed: i32, colors: usize, data: &[u8]) -> Self {
+        let opaque = || data.chunks_exact(4).filter(|pix| pix[3] != 0);
+        let stride = sample_speed(speed, opaque().count()) as usize;
+
+        let mut histogram = vec![(0u64, [0u64; 3]); 1 << 15];
+        for pix in opaque().step_by(stride) {
+            let (count, sum) = &mut histogram[bucket_key(pix)];
+            *count += 1;
+            for c in 0..3 {
+                sum[c] += pix[c] as u64;
+            }
+        }
+
+        let mut buckets: Vec<Bucket> = histogram
+            .iter()
+            .enumerate()
+            .filter(|(_, (count, _))| *count > 0)
+            .map(|(key, &(count, sum))| Bucket { key, count, sum })
+            .collect();
+
+        let mut boxes = Vec::with_capacity(colors);
+        boxes.push(0..buckets.len());
+        while boxes.len() < colors.max(1) {
+            // the splittable box with the widest range along any channel
+            let widest = boxes
+                .iter()
+                .enumerate()
+                .filter(|(_, range)| range.len() > 1)
+                .map(|(i, range)| {
+                    let (channel, extent) = widest_channel(&buckets[range.clone()]);
+                    (i, channel, extent)
+                })
+                .max_by_key(|&(_, _, extent)| extent);
+
+            let Some((i, channel, _)) = widest else {
+                break;
+            };
+
+            let range = boxes[i].clone();
+            let bucket = &mut buckets[range.clone()];
+            bucket.sort_unstable_by_key(|b| b.channel(channel));
+
+            // split at the weighted median, keeping both halves non-empty
+            let half = bucket.iter().map(|b| b.count).sum::<u64>() / 2;
+            let mut seen = 0;
+            let mut split = 1;
+            for (j, b) in bucket.iter().enumerate() {
+                seen += b.count;
+                if seen >= half {
+                    split = j.clamp(1, bucket.len() - 1);
+                    break;
+                }
+            }
+
+            boxes[i] = range.start..range.start + split;
+            boxes.push(range.start + split..range.end);
+        }
+
+        let mut palette: Vec<u8> = boxes
+            .iter()
+            .filter(|range| !range.is_empty())
+            .flat_map(|range| {
+                let count: u64 = buckets[range.clone()].iter().map(|b| b.count).sum();
+                let avg = |c: usize| {
+                    let sum: u64 = buckets[range.clone()].iter().map(|b| b.sum[c]).sum();
+                    (sum / count) as u8
+                };
+                [avg(0), avg(1), avg(2)]
+            })
+            .collect();
+
+        if palette.is_empty() {
+            palette.extend_from_slice(&[0, 0, 0]);
+        }
+
+        // only the buckets the palette was learned from, the rest are searched for
+        // when a pixel falls into them
+        let mut lookup = vec![NOT_CACHED; 1 << 15];
+        for bucket in &buckets {
+            lookup[bucket.key] = nearest_color(&palette, bucket.key) as u16;
+        }
+
+        Self { palette, lookup }
+    }
+
+    pub fn palette(&self) -> &[u8] {
+        &self.palette
+    }
+
+    pub fn index_of(&self, pix: &[u8]) -> u8 {
+        let key = bucket_key(pix);
+        match self.lookup[key] {
+            NOT_CACHED => nearest_color(&self.palette, key),
+            index => index as u8,
+        }
+    }
+}
+
+impl Quantize for MedianCut {
+    fn palette(&self) -> &[u8] {
+        MedianCut::palette(self)
+    }
+
+    fn index_of(&self, pix: &[u8]) -> u8 {
+        MedianCut::index_of(self, pix)
+    }
+}
+
+// The palette entry closest to the center of a bucket
+fn nearest_color(palette: &[u8], key: usize) -> u8 {
+    let center = [
+        ((key >> 10) << 3 | 4) as i32,
+        ((key >> 5 & 0x1F) << 3 | 4) as i32,
+        ((key & 0x1F) << 3 | 4) as i32,
+    ];
+
+    palette
+        .chunks_exact(3)
+        .enumerate()
+        .min_by_key(|(_, color)| {
+            (0..3)
+                .map(|c| (color[c] as i32 - center[c]).pow(2))
+                .sum::<i32>()
+        })
+        .map_or(0, |(i, _)| i as u8)
+}
+
+// The channel with the widest range of values in a set of buckets
+fn widest_channel(buckets: &[Bucket]) -> (usize, usize) {
+    (0..3)
+        .map(|c| {
+            let min = buckets.iter().map(|b| b.channel(c)).min().unwrap_or(0);
+            let max = buckets.iter().map(|b| b.channel(c)).max().unwrap_or(0);
+            (c, max - min)
+        })
+        .max_by_key(|&(_, extent)| extent)
+        .unwrap_or((0, 0))
+}
+
+// The 216 color web safe palette, every combination of 0, 51, 102, 153, 204
+// and 255 per channel, followed by a black slot used for transparency
+fn web_safe_palette() -> Vec<u8> {
+    let mut palette = Vec::with_capacity(217 * 3);
+    for r in 0..6 {
+        for g in 0..6 {
+            for b in 0..6 {
+                palette.extend_from_slice(&[r * 51, g * 51, b * 51]);
+            }
+        }
+    }
+
+    palette.extend_from_slice(&[0, 0, 0]);
+    palette
+}
+
+// Rounds each channel to the nearest web safe level, fully transparent
+// pixels map to the transparency slot
+fn web_safe_index_of(pix: &[u8]) -> u8 {
+    if pix.get(3) == Some(&0) {
+        return WEB_SAFE_TRANSPARENT;
+    }
+
+    let level = |c: u8| (c as u16 + 25) / 51;
+    (level(pix[0]) * 36 + level(pix[1]) * 6 + level(pix[2])) as u8
+}
+
+pub(crate) const WEB_SAFE_TRANSPARENT: u8 = 216;
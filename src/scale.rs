@@ -0,0 +1,108 @@
+use crate::{check_buffer_len, GifError};
+
+// The filter used to resize rgba frames
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResizeFilter {
+    Nearest,
+    #[default]
+    Bilinear,
+}
+
+// Resizes an rgba buffer of src_w x src_h pixels to dst_w x dst_h pixels, e.g. to
+// scale frames before from_rgba. bilinear weighs colors by their alpha, so
+// transparent pixels don't bleed black into the edges next to them
+// panics when src isn't exactly src_w * src_h pixels or the source is empty
+pub fn resize_rgba(
+    src: &[u8],
+    src_w: u16,
+    src_h: u16,
+    dst_w: u16,
+    dst_h: u16,
+    filter: ResizeFilter,
+) -> Vec<u8> {
+    match try_resize_rgba(src, src_w, src_h, dst_w, dst_h, filter) {
+        Ok(resized) => resized,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+// same as resize_rgba, but returns an error instead of panicking
+// an empty destination is just an empty buffer
+pub fn try_resize_rgba(
+    src: &[u8],
+    src_w: u16,
+    src_h: u16,
+    dst_w: u16,
+    dst_h: u16,
+    filter: ResizeFilter,
+) -> Result<Vec<u8>, GifError> {
+    if src_w == 0 || src_h == 0 {
+        return Err(GifError::EmptyFrame);
+    }
+
+    check_buffer_len(src_w, src_h, 4, src)?;
+    Ok(resize(src, src_w, src_h, dst_w, dst_h, filter))
+}
+
+fn resize(
+    src: &[u8],
+    src_w: u16,
+    src_h: u16,
+    dst_w: u16,
+    dst_h: u16,
+    filter: ResizeFilter,
+) -> Vec<u8> {
+    let (sw, sh) = (src_w as usize, src_h as usize);
+    let (dw, dh) = (dst_w as usize, dst_h as usize);
+
+    if (sw, sh) == (dw, dh) {
+        return src.to_vec();
+    }
+
+    let mut out = Vec::with_capacity(dw * dh * 4);
+    for y in 0..dh {
+        for x in 0..dw {
+            match filter {
+                ResizeFilter::Nearest => {
+                    let sx = x * sw / dw;
+                    let sy = y * sh / dh;
+                    let i = (sy * sw + sx) * 4;
+                    out.extend_from_slice(&src[i..i + 4]);
+                }
+                ResizeFilter::Bilinear => {
+                    // sample at pixel centers
+                    let fx = ((x as f32 + 0.5) * sw as f32 / dw as f32 - 0.5).max(0.0);
+                    let fy = ((y as f32 + 0.5) * sh as f32 / dh as f32 - 0.5).max(0.0);
+                    let (x0, y0) = (fx as usize, fy as usize);
+                    let (x1, y1) = ((x0 + 1).min(sw - 1), (y0 + 1).min(sh - 1));
+                    let (tx, ty) = (fx - x0 as f32, fy - y0 as f32);
+
+                    // colors are averaged weighted by alpha as well, a transparent
+                    // pixel's color is meaningless and shouldn't contribute any
+                    let mut sum = [0.0f32; 4];
+                    for (x, y, weight) in [
+                        (x0, y0, (1.0 - tx) * (1.0 - ty)),
+                        (x1, y0, tx * (1.0 - ty)),
+                        (x0, y1, (1.0 - tx) * ty),
+                        (x1, y1, tx * ty),
+                    ] {
+                        let pix = &src[(y * sw + x) * 4..][..4];
+                        let alpha = pix[3] as f32 * weight;
+                        for c in 0..3 {
+                            sum[c] += pix[c] as f32 * alpha;
+                        }
+                        sum[3] += alpha;
+                    }
+
+                    for c in 0..3 {
+                        let color = if sum[3] > 0.0 { sum[c] / sum[3] } else { 0.0 };
+                        out.push(color.round().min(255.0) as u8);
+                    }
+                    out.push(sum[3].round().min(255.0) as u8);
+                }
+            }
+        }
+    }
+
+    out
+}
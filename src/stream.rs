@@ -0,0 +1,1240 @@
+use std::error::Error;
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use async_stream::try_stream;
+use futures::{Future, Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::sync::mpsc;
+use tokio::time::{Interval, MissedTickBehavior};
+
+use crate::{
+    check_buffer_len, frame_delay, mask_unchanged, DisposalMethod, Frame, GifEncoder, GifError,
+    GifStream, GlobalPalette, LocalPalette, PaletteCache, Quantize, StreamError,
+};
+
+impl<S, F> GifStream<S, F> {
+    // missed_tick sets what happens when generating a frame takes longer than the interval
+    // defaults to MissedTickBehavior::Burst, which catches up with back to back frames
+    // Skip drops the missed ticks and keeps the stream in step with the wall clock
+    pub fn missed_tick(mut self, behavior: MissedTickBehavior) -> Self {
+        self.missed_tick = behavior;
+        self
+    }
+
+    // the interval frames are generated at
+    fn ticker(&self) -> Interval {
+        let mut interval = tokio::time::interval(self.interval);
+        interval.set_missed_tick_behavior(self.missed_tick);
+        interval
+    }
+
+    // calls the generator, retrying on the next tick after an error until
+    // max_consecutive_errors errors in a row, then returns the last one
+    async fn generate<T, E, R>(&mut self, interval: &mut Interval) -> Result<T, E>
+    where
+        S: Clone,
+        F: Fn(S) -> R,
+        R: Future<Output = Result<T, E>>,
+    {
+        let mut failures = 0;
+        loop {
+            match (self.generator)(self.state.clone()).await {
+                Ok(data) => return Ok(data),
+                Err(e) => {
+                    failures += 1;
+                    if failures >= self.max_consecutive_errors {
+                        return Err(e);
+                    }
+                }
+            }
+
+            interval.tick().await;
+        }
+    }
+}
+
+// picks the speed and color count of stream_adaptive from a moving average
+// of the encoded frame sizes, against the bytes one interval is allowed
+struct BitrateController {
+    budget: f64,
+    average: Option<f64>,
+    min_speed: i32,
+    speed: i32,
+    colors: usize,
+}
+
+impl BitrateController {
+    fn new(target_bitrate: usize, interval: Duration, speed: i32) -> Self {
+        Self {
+            budget: target_bitrate as f64 * interval.as_secs_f64(),
+            average: None,
+            min_speed: speed,
+            speed,
+            colors: 256,
+        }
+    }
+
+    fn record(&mut self, size: usize) {
+        let size = size as f64;
+        let average = match self.average {
+            Some(average) => average * 0.75 + size * 0.25,
+            None => size,
+        };
+        self.average = Some(average);
+
+        if average > self.budget {
+            self.colors = (self.colors / 2).max(2);
+            self.speed = (self.speed + 5).min(30);
+        } else if average < self.budget / 2.0 {
+            self.colors = (self.colors * 2).min(256);
+            self.speed = (self.speed - 5).max(self.min_speed);
+        }
+    }
+}
+
+impl<D> GifStream<mpsc::Receiver<D>, ()>
+where
+    D: AsRef<[u8]>,
+{
+    // receiver constructor, frames are pushed by a producer through the channel
+    // instead of being pulled from a generator every interval
+    // frame_delay is only the displayed delay, pacing comes from the sender
+    pub fn from_receiver(
+        frame_delay: Duration,
+        width: u16,
+        height: u16,
+        receiver: mpsc::Receiver<D>,
+    ) -> Self {
+        Self::with_defaults(frame_delay, width, height, receiver, ())
+    }
+
+    // received stream, assumes no global palette
+    // encodes each frame as it arrives, a bounded channel applies backpressure
+    // to the producer when the consumer is slow
+    // ends with the trailer once every sender is dropped
+    pub fn stream_received(mut self) -> impl Stream<Item = Result<Vec<u8>, GifError>> {
+        let coalesce = self.coalesce;
+        let stream = try_stream! {
+            yield Chunk::Header(self.header_bytes(&[]));
+
+            let mut cache = PaletteCache::default();
+            let mut encoder = self.encoder();
+            let mut delays = self.delays();
+            for _ in 0..self.frames.unwrap_or(usize::MAX) {
+                let Some(data) = self.state.recv().await else {
+                    break;
+                };
+
+                let mut buf = Vec::new();
+
+                self.write_local_frame(
+                    &mut buf,
+                    data.as_ref(),
+                    delays.next(),
+                    &mut cache,
+                    &mut encoder,
+                    &mut |_| {},
+                )?;
+
+                yield Chunk::Frame(buf);
+            }
+
+            yield Chunk::Trailer;
+        };
+
+        coalesce_frames(stream, coalesce)
+    }
+}
+
+impl<S, F, D, E, R> GifStream<S, F>
+where
+    S: Clone + Send,
+    F: Fn(S) -> R,
+    R: Future<Output = Result<D, E>> + Send + 'static,
+    D: AsRef<[u8]>,
+{
+    // default stream, assumes no global palette
+    // returns a stream of encoded gif frames
+    pub fn stream(self) -> impl Stream<Item = Result<Vec<u8>, StreamError<E>>> {
+        self.stream_inspect(|_| {})
+    }
+
+    // same as stream, but calls on_frame with every frame before it's encoded,
+    // e.g. to see the local palette and transparent index each one ended up with,
+    // or to hand the index buffer to another consumer without quantizing twice
+    pub fn stream_inspect<P: FnMut(&Frame)>(
+        mut self,
+        mut on_frame: P,
+    ) -> impl Stream<Item = Result<Vec<u8>, StreamError<E>>> {
+        let coalesce = self.coalesce;
+        let stream = try_stream! {
+            yield Chunk::Header(self.header_bytes(&[]));
+
+            let mut cache = PaletteCache::default();
+            let mut encoder = self.encoder();
+            let mut delays = self.delays();
+            let mut interval = self.ticker();
+            for _ in 0..self.frames.unwrap_or(usize::MAX) {
+                interval.tick().await;
+
+                let mut buf = Vec::new();
+
+                let data = self.generate(&mut interval).await.map_err(StreamError::Generator)?;
+                self.write_local_frame(
+                    &mut buf,
+                    data.as_ref(),
+                    delays.next(),
+                    &mut cache,
+                    &mut encoder,
+                    &mut on_frame,
+                )?;
+
+                yield Chunk::Frame(buf);
+            }
+
+            if self.frames.is_some() {
+                yield Chunk::Trailer;
+            }
+        };
+
+        coalesce_frames(stream, coalesce)
+    }
+
+    // same as stream, but every frame is mapped onto the palette learn returns for it
+    // to plug in a custom Quantize, see Frame::from_rgba_quantized
+    // palette_refresh, dithering and normalize_alpha are up to the quantizer
+    pub fn stream_quantized<Q: Quantize, L: FnMut(&[u8]) -> Q>(
+        mut self,
+        mut learn: L,
+    ) -> impl Stream<Item = Result<Vec<u8>, StreamError<E>>> {
+        let coalesce = self.coalesce;
+        let stream = try_stream! {
+            yield Chunk::Header(self.header_bytes(&[]));
+
+            let mut encoder = self.encoder();
+            let mut delays = self.delays();
+            let mut interval = self.ticker();
+            for _ in 0..self.frames.unwrap_or(usize::MAX) {
+                interval.tick().await;
+
+                let mut buf = Vec::new();
+
+                let data = self.generate(&mut interval).await.map_err(StreamError::Generator)?;
+                let data = data.as_ref();
+                check_buffer_len(self.width, self.height, 4, data)?;
+
+                let quantizer = learn(data);
+                let mut frame = Frame::from_rgba_quantized(self.width, self.height, data, &quantizer);
+                if self.trim_transparent {
+                    frame.trim_transparent();
+                }
+
+                encoder.try_write_frame(
+                    &mut buf,
+                    &frame,
+                    delays.next(),
+                    self.interlaced,
+                    self.dispose,
+                )?;
+
+                yield Chunk::Frame(buf);
+            }
+
+            if self.frames.is_some() {
+                yield Chunk::Trailer;
+            }
+        };
+
+        coalesce_frames(stream, coalesce)
+    }
+
+    // same as stream, but a frame is made whenever ticks yields instead of on the
+    // interval, e.g. to drive frames off external events or feed ticks by hand in tests
+    // an error skips its tick, see max_consecutive_errors
+    // ends with the trailer once ticks ends or take_frames frames were sent
+    pub fn stream_ticked<T: Stream<Item = ()>>(
+        self,
+        ticks: T,
+    ) -> impl Stream<Item = Result<Vec<u8>, StreamError<E>>> {
+        let coalesce = self.coalesce;
+        let stream = try_stream! {
+            yield Chunk::Header(self.header_bytes(&[]));
+
+            let mut cache = PaletteCache::default();
+            let mut encoder = self.encoder();
+            let mut delays = self.delays();
+            let mut failures = 0;
+            let mut sent = 0;
+            let mut ticks = std::pin::pin!(ticks);
+            while sent < self.frames.unwrap_or(usize::MAX) && ticks.next().await.is_some() {
+                let data = match (self.generator)(self.state.clone()).await {
+                    Ok(data) => data,
+                    Err(e) => {
+                        failures += 1;
+                        if failures >= self.max_consecutive_errors {
+                            Err(StreamError::Generator(e))?;
+                        }
+                        continue;
+                    }
+                };
+                failures = 0;
+
+                let mut buf = Vec::new();
+
+                self.write_local_frame(
+                    &mut buf,
+                    data.as_ref(),
+                    delays.next(),
+                    &mut cache,
+                    &mut encoder,
+                    &mut |_| {},
+                )?;
+
+                sent += 1;
+                yield Chunk::Frame(buf);
+            }
+
+            yield Chunk::Trailer;
+        };
+
+        coalesce_frames(stream, coalesce)
+    }
+
+    // same as stream, but the interval is read from interval_ms (in milliseconds)
+    // before every frame, so the cadence can be changed mid stream, e.g. slowed down
+    // under load. the displayed delay follows, a new interval counts from the last frame
+    pub fn stream_adjustable(
+        mut self,
+        interval_ms: Arc<AtomicU64>,
+    ) -> impl Stream<Item = Result<Vec<u8>, StreamError<E>>> {
+        let coalesce = self.coalesce;
+        let stream = try_stream! {
+            yield Chunk::Header(self.header_bytes(&[]));
+
+            let mut cache = PaletteCache::default();
+            let mut encoder = self.encoder();
+            let mut delays = self.delays();
+            let mut interval = self.ticker();
+            for _ in 0..self.frames.unwrap_or(usize::MAX) {
+                // tokio intervals can't be zero
+                let period = Duration::from_millis(interval_ms.load(Ordering::Relaxed).max(1));
+                if period != self.interval {
+                    self.interval = period;
+                    delays.set_interval(period);
+
+                    // a fresh interval ticks right away, reset waits a period first
+                    interval = self.ticker();
+                    interval.reset();
+                }
+
+                interval.tick().await;
+
+                let mut buf = Vec::new();
+
+                let data = self.generate(&mut interval).await.map_err(StreamError::Generator)?;
+                self.write_local_frame(
+                    &mut buf,
+                    data.as_ref(),
+                    delays.next(),
+                    &mut cache,
+                    &mut encoder,
+                    &mut |_| {},
+                )?;
+
+                yield Chunk::Frame(buf);
+            }
+
+            if self.frames.is_some() {
+                yield Chunk::Trailer;
+            }
+        };
+
+        coalesce_frames(stream, coalesce)
+    }
+
+    // default stream as an AsyncRead, e.g. to tokio::io::copy the gif into any writer
+    pub fn reader(self) -> GifReader<impl Stream<Item = Result<Vec<u8>, StreamError<E>>>> {
+        GifReader::new(self.stream())
+    }
+
+    // default stream, yielding bytes::Bytes instead of Vec<u8>
+    #[cfg(feature = "bytes")]
+    pub fn stream_bytes(self) -> impl Stream<Item = Result<bytes::Bytes, StreamError<E>>> {
+        bytes_stream(self.stream())
+    }
+
+    // drives a bounded default stream to completion, writing every chunk to w
+    // the written gif ends with the trailer
+    pub async fn write_all_to<W: AsyncWrite + Unpin>(
+        self,
+        w: &mut W,
+        frames: usize,
+    ) -> Result<(), StreamError<E>> {
+        let stream = self.take_frames(frames).stream();
+        futures::pin_mut!(stream);
+
+        while let Some(chunk) = stream.next().await {
+            w.write_all(&chunk?).await.map_err(StreamError::Io)?;
+        }
+
+        w.flush().await.map_err(StreamError::Io)
+    }
+
+    // same as write_all_to, but creates (or truncates) the file at path
+    pub async fn write_to_file(
+        self,
+        path: impl AsRef<Path>,
+        frames: usize,
+    ) -> Result<(), StreamError<E>> {
+        let mut file = tokio::fs::File::create(path)
+            .await
+            .map_err(StreamError::Io)?;
+        self.write_all_to(&mut file, frames).await
+    }
+
+    // bounded stream, assumes no global palette
+    // returns a stream of `frames` encoded gif frames followed by the trailer
+    pub fn stream_bounded(
+        self,
+        frames: usize,
+    ) -> impl Stream<Item = Result<Vec<u8>, StreamError<E>>> {
+        self.take_frames(frames).stream()
+    }
+
+    // cancellable stream, assumes no global palette
+    // ends with the trailer as soon as `shutdown` completes, so whatever was
+    // sent so far forms a valid gif, e.g. a oneshot::Receiver or CancellationToken::cancelled()
+    pub fn stream_until<C: Future>(
+        mut self,
+        shutdown: C,
+    ) -> impl Stream<Item = Result<Vec<u8>, StreamError<E>>> {
+        let coalesce = self.coalesce;
+        let stream = try_stream! {
+            yield Chunk::Header(self.header_bytes(&[]));
+
+            tokio::pin!(shutdown);
+            let mut cache = PaletteCache::default();
+            let mut encoder = self.encoder();
+            let mut delays = self.delays();
+            let mut interval = self.ticker();
+            for _ in 0..self.frames.unwrap_or(usize::MAX) {
+                tokio::select! {
+                    biased;
+                    _ = &mut shutdown => break,
+                    _ = interval.tick() => {}
+                }
+
+                let mut buf = Vec::new();
+
+                let data = self.generate(&mut interval).await.map_err(StreamError::Generator)?;
+                self.write_local_frame(
+                    &mut buf,
+                    data.as_ref(),
+                    delays.next(),
+                    &mut cache,
+                    &mut encoder,
+                    &mut |_| {},
+                )?;
+
+                yield Chunk::Frame(buf);
+            }
+
+            yield Chunk::Trailer;
+        };
+
+        coalesce_frames(stream, coalesce)
+    }
+
+    // adaptive stream, assumes no global palette
+    // keeps the output under target_bitrate bytes per second of interval by
+    // learning fewer colors at a higher speed while recent frames run over budget,
+    // and winning them back once frames are well under it
+    // the speed set on the stream is the best quality it goes up to
+    pub fn stream_adaptive(
+        mut self,
+        target_bitrate: usize,
+    ) -> impl Stream<Item = Result<Vec<u8>, StreamError<E>>> {
+        let coalesce = self.coalesce;
+        let stream = try_stream! {
+            yield Chunk::Header(self.header_bytes(&[]));
+
+            let mut bitrate = BitrateController::new(target_bitrate, self.interval, self.speed);
+            let mut encoder = self.encoder();
+            let mut delays = self.delays();
+            let mut interval = self.ticker();
+            for _ in 0..self.frames.unwrap_or(usize::MAX) {
+                interval.tick().await;
+
+                let mut buf = Vec::new();
+
+                let data = self.generate(&mut interval).await.map_err(StreamError::Generator)?;
+                check_buffer_len(self.width, self.height, 4, data.as_ref())?;
+
+                let lp = LocalPalette::with_colors(
+                    self.quantizer,
+                    bitrate.speed,
+                    bitrate.colors,
+                    data.as_ref(),
+                    1,
+                );
+                let frame = self.palette_frame(data.as_ref(), &lp);
+
+                encoder.try_write_frame(
+                    &mut buf,
+                    &frame,
+                    delays.next(),
+                    self.interlaced,
+                    self.dispose,
+                )?;
+
+                bitrate.record(buf.len());
+                yield Chunk::Frame(buf);
+            }
+
+            if self.frames.is_some() {
+                yield Chunk::Trailer;
+            }
+        };
+
+        coalesce_frames(stream, coalesce)
+    }
+
+    // diffed stream, assumes no global palette
+    // after the first frame, pixels that are unchanged from the previous
+    // frame are encoded as transparent, which shrinks mostly static frames
+    // every frame is quantized like in stream, with the quantizer, dithering,
+    // palette refresh and trim_transparent settings of the stream
+    // always uses DisposalMethod::Keep so unchanged pixels show through
+    pub fn stream_diffed(mut self) -> impl Stream<Item = Result<Vec<u8>, StreamError<E>>> {
+        let coalesce = self.coalesce;
+        let stream = try_stream! {
+            yield Chunk::Header(self.header_bytes(&[]));
+
+            let mut cache = PaletteCache::default();
+            let mut prev: Option<Vec<u8>> = None;
+            let mut encoder = self.encoder();
+            let mut delays = self.delays();
+            let mut interval = self.ticker();
+            for _ in 0..self.frames.unwrap_or(usize::MAX) {
+                interval.tick().await;
+
+                let mut buf = Vec::new();
+
+                let data = self.generate(&mut interval).await.map_err(StreamError::Generator)?;
+                let data = data.as_ref();
+                check_buffer_len(self.width, self.height, 4, data)?;
+                let frame = match &prev {
+                    Some(prev) => self.local_frame(&mask_unchanged(prev, data), &mut cache)?,
+                    None => self.local_frame(data, &mut cache)?,
+                };
+
+                encoder.try_write_frame(
+                    &mut buf,
+                    &frame,
+                    delays.next(),
+                    self.interlaced,
+                    DisposalMethod::Keep,
+                )?;
+
+                prev = Some(data.to_vec());
+                yield Chunk::Frame(buf);
+            }
+
+            if self.frames.is_some() {
+                yield Chunk::Trailer;
+            }
+        };
+
+        coalesce_frames(stream, coalesce)
+    }
+
+    // keyframed stream, assumes no global palette
+    // like stream_diffed, but every keyframe_every frames (and the first) is sent
+    // in full, so a viewer joining mid stream has a whole picture to start from
+    // and quantization errors don't pile up, 0 only sends the first one in full
+    // diffs are taken against what the viewer shows, every pixel drawn so far,
+    // rather than the last frame, so pixels a frame couldn't clear aren't resent
+    // keyframes and diffs are quantized with the stream's settings, like stream_diffed
+    // always uses DisposalMethod::Keep so unchanged pixels show through
+    pub fn stream_keyframed(
+        mut self,
+        keyframe_every: usize,
+    ) -> impl Stream<Item = Result<Vec<u8>, StreamError<E>>> {
+        let coalesce = self.coalesce;
+        let stream = try_stream! {
+            yield Chunk::Header(self.header_bytes(&[]));
+
+            let mut cache = PaletteCache::default();
+            let mut shown: Vec<u8> = Vec::new();
+            let mut encoder = self.encoder();
+            let mut delays = self.delays();
+            let mut interval = self.ticker();
+            for n in 0..self.frames.unwrap_or(usize::MAX) {
+                interval.tick().await;
+
+                let mut buf = Vec::new();
+
+                let data = self.generate(&mut interval).await.map_err(StreamError::Generator)?;
+                let data = data.as_ref();
+                check_buffer_len(self.width, self.height, 4, data)?;
+
+                let keyframe = n == 0 || (keyframe_every > 0 && n.is_multiple_of(keyframe_every));
+                let frame = if keyframe {
+                    self.local_frame(data, &mut cache)?
+                } else {
+                    self.local_frame(&mask_unchanged(&shown, data), &mut cache)?
+                };
+
+                encoder.try_write_frame(
+                    &mut buf,
+                    &frame,
+                    delays.next(),
+                    self.interlaced,
+                    DisposalMethod::Keep,
+                )?;
+
+                // the colors the viewer decodes, so pixels the palette got wrong
+                // differ from the next frame's data and are sent again
+                if shown.is_empty() {
+                    shown = vec![0; data.len()];
+                }
+                draw_frame(&mut shown, self.width, &frame);
+
+                yield Chunk::Frame(buf);
+            }
+
+            if self.frames.is_some() {
+                yield Chunk::Trailer;
+            }
+        };
+
+        coalesce_frames(stream, coalesce)
+    }
+
+    // stream with global palette
+    // returns a stream of encoded gif frames
+    pub fn stream_with_palette(
+        mut self,
+        gp: GlobalPalette,
+    ) -> impl Stream<Item = Result<Vec<u8>, StreamError<E>>> {
+        let coalesce = self.coalesce;
+        let stream = try_stream! {
+            yield Chunk::Header(self.header_bytes(gp.palette()));
+
+            let mut encoder = self.palette_encoder(gp.palette().len() / 3);
+            let mut delays = self.delays();
+            let mut interval = self.ticker();
+            for _ in 0..self.frames.unwrap_or(usize::MAX) {
+                interval.tick().await;
+
+                let mut buf = Vec::new();
+
+                let data = self.generate(&mut interval).await.map_err(StreamError::Generator)?;
+                check_buffer_len(self.width, self.height, 4, data.as_ref())?;
+                let frame = Frame::with_global_palette_rgba(self.width, self.height, data.as_ref(), &gp);
+
+                encoder.try_write_frame(
+                    &mut buf,
+                    &frame,
+                    delays.next(),
+                    self.interlaced,
+                    self.dispose,
+                )?;
+
+                yield Chunk::Frame(buf);
+            }
+
+            if self.frames.is_some() {
+                yield Chunk::Trailer;
+            }
+        };
+
+        coalesce_frames(stream, coalesce)
+    }
+
+    // stream with auto generated global palette, given a number of colors
+    pub fn stream_auto_palette(
+        self,
+        n_colors: usize,
+    ) -> impl Stream<Item = Result<Vec<u8>, StreamError<E>>> {
+        self.stream_auto_palette_inspect(n_colors, |_| {})
+    }
+
+    // same as stream_auto_palette, but calls on_palette with the palette once it's
+    // learned (and again after every relearn), e.g. to check its transparent index
+    pub fn stream_auto_palette_inspect<P: FnMut(&GlobalPalette)>(
+        mut self,
+        n_colors: usize,
+        mut on_palette: P,
+    ) -> impl Stream<Item = Result<Vec<u8>, StreamError<E>>> {
+        let coalesce = self.coalesce;
+        let stream = try_stream! {
+            let mut interval = self.ticker();
+            let data = self.generate(&mut interval).await.map_err(StreamError::Generator)?;
+            check_buffer_len(self.width, self.height, 4, data.as_ref())?;
+            let mut gp = GlobalPalette::try_with_quantizer(
+                self.quantizer,
+                self.speed,
+                n_colors,
+                data.as_ref(),
+            )?;
+            on_palette(&gp);
+
+            yield Chunk::Header(self.header_bytes(gp.palette()));
+
+            // relearned palettes have at most n_colors either
+            let mut encoder = self.palette_encoder(n_colors);
+            let mut delays = self.delays();
+            let mut relearned = false;
+            for n in 0..self.frames.unwrap_or(usize::MAX) {
+                interval.tick().await;
+
+                let mut buf = Vec::new();
+
+                let data = self.generate(&mut interval).await.map_err(StreamError::Generator)?;
+                check_buffer_len(self.width, self.height, 4, data.as_ref())?;
+
+                // the global color table can't change once sent, so every frame
+                // after the first relearn carries the palette as a local color table
+                if self.relearn_every > 0 && n > 0 && n.is_multiple_of(self.relearn_every) {
+                    gp = GlobalPalette::try_with_quantizer(
+                        self.quantizer,
+                        self.speed,
+                        n_colors,
+                        data.as_ref(),
+                    )?;
+                    on_palette(&gp);
+                    relearned = true;
+                }
+
+                let mut frame = Frame::with_global_palette_rgba(self.width, self.height, data.as_ref(), &gp);
+                if relearned {
+                    frame.palette = Some(gp.palette().to_vec());
+                }
+
+                encoder.try_write_frame(
+                    &mut buf,
+                    &frame,
+                    delays.next(),
+                    self.interlaced,
+                    self.dispose,
+                )?;
+
+                yield Chunk::Frame(buf);
+            }
+
+            if self.frames.is_some() {
+                yield Chunk::Trailer;
+            }
+        };
+
+        coalesce_frames(stream, coalesce)
+    }
+
+    // same as stream_auto_palette, but holds back the first `samples` frames
+    // and learns the palette from all of them, then sends them and carries on
+    // useful when the first frame isn't representative, e.g. a fade in from black
+    pub fn stream_auto_palette_sampled(
+        mut self,
+        n_colors: usize,
+        samples: usize,
+    ) -> impl Stream<Item = Result<Vec<u8>, StreamError<E>>> {
+        let coalesce = self.coalesce;
+        let stream = try_stream! {
+            let mut interval = self.ticker();
+            let mut sampled = Vec::with_capacity(samples);
+            for _ in 0..samples.max(1) {
+                interval.tick().await;
+
+                let data = self.generate(&mut interval).await.map_err(StreamError::Generator)?;
+                check_buffer_len(self.width, self.height, 4, data.as_ref())?;
+                sampled.push(data);
+            }
+
+            let frames: Vec<&[u8]> = sampled.iter().map(|data| data.as_ref()).collect();
+            let gp = GlobalPalette::try_with_quantizer(
+                self.quantizer,
+                self.speed,
+                n_colors,
+                &frames.concat(),
+            )?;
+
+            yield Chunk::Header(self.header_bytes(gp.palette()));
+
+            let mut encoder = self.palette_encoder(n_colors);
+            let mut delays = self.delays();
+            let mut sampled = sampled.into_iter();
+            for _ in 0..self.frames.unwrap_or(usize::MAX) {
+                let data = match sampled.next() {
+                    Some(data) => data,
+                    None => {
+                        interval.tick().await;
+                        let data = self.generate(&mut interval).await.map_err(StreamError::Generator)?;
+                        check_buffer_len(self.width, self.height, 4, data.as_ref())?;
+                        data
+                    }
+                };
+
+                let mut buf = Vec::new();
+
+                let frame = Frame::with_global_palette_rgba(self.width, self.height, data.as_ref(), &gp);
+
+                encoder.try_write_frame(
+                    &mut buf,
+                    &frame,
+                    delays.next(),
+                    self.interlaced,
+                    self.dispose,
+                )?;
+
+                yield Chunk::Frame(buf);
+            }
+
+            if self.frames.is_some() {
+                yield Chunk::Trailer;
+            }
+        };
+
+        coalesce_frames(stream, coalesce)
+    }
+}
+
+impl<S, F, D, E, R> GifStream<S, F>
+where
+    S: Clone + Send,
+    F: Fn(S) -> R,
+    R: Future<Output = Result<(D, Duration), E>> + Send + 'static,
+    D: AsRef<[u8]>,
+{
+    // timed stream, assumes no global palette
+    // the generator returns each frame along with how long it should be displayed
+    // the displayed delay is independent of the interval frames are generated at
+    pub fn stream_timed(mut self) -> impl Stream<Item = Result<Vec<u8>, StreamError<E>>> {
+        let coalesce = self.coalesce;
+        let stream = try_stream! {
+            yield Chunk::Header(self.header_bytes(&[]));
+
+            let mut cache = PaletteCache::default();
+            let mut encoder = self.encoder();
+            let mut interval = self.ticker();
+            for _ in 0..self.frames.unwrap_or(usize::MAX) {
+                interval.tick().await;
+
+                let mut buf = Vec::new();
+
+                let (data, delay) = self.generate(&mut interval).await.map_err(StreamError::Generator)?;
+                self.write_local_frame(
+                    &mut buf,
+                    data.as_ref(),
+                    frame_delay(delay).max(self.min_delay),
+                    &mut cache,
+                    &mut encoder,
+                    &mut |_| {},
+                )?;
+
+                yield Chunk::Frame(buf);
+            }
+
+            if self.frames.is_some() {
+                yield Chunk::Trailer;
+            }
+        };
+
+        coalesce_frames(stream, coalesce)
+    }
+}
+
+impl<S, F, D, E, R> GifStream<S, F>
+where
+    S: Clone + Send,
+    F: Fn(S) -> R,
+    R: Future<Output = Result<Option<D>, E>> + Send + 'static,
+    D: AsRef<[u8]>,
+{
+    // sparse stream, assumes no global palette
+    // the generator returns None when there is nothing new to show, in which
+    // case nothing is sent that tick and the previous frame stays on screen
+    // take_frames counts only the frames that were actually sent
+    pub fn stream_sparse(mut self) -> impl Stream<Item = Result<Vec<u8>, StreamError<E>>> {
+        let coalesce = self.coalesce;
+        let stream = try_stream! {
+            yield Chunk::Header(self.header_bytes(&[]));
+
+            let mut cache = PaletteCache::default();
+            let mut encoder = self.encoder();
+            let mut delays = self.delays();
+            let mut interval = self.ticker();
+            let mut sent = 0;
+            while self.frames.is_none_or(|n| sent < n) {
+                interval.tick().await;
+
+                let data = self.generate(&mut interval).await.map_err(StreamError::Generator)?;
+                let Some(data) = data else {
+                    continue;
+                };
+
+                let mut buf = Vec::new();
+
+                self.write_local_frame(
+                    &mut buf,
+                    data.as_ref(),
+                    delays.next(),
+                    &mut cache,
+                    &mut encoder,
+                    &mut |_| {},
+                )?;
+
+                sent += 1;
+                yield Chunk::Frame(buf);
+            }
+
+            yield Chunk::Trailer;
+        };
+
+        coalesce_frames(stream, coalesce)
+    }
+}
+
+impl<S, F, E, R> GifStream<S, F>
+where
+    S: Clone + Send,
+    F: Fn(S) -> R,
+    R: Future<Output = Result<Frame, E>> + Send + 'static,
+{
+    // frame stream, the generator returns already quantized frames
+    // each frame keeps its own size, position and palette, only the screen
+    // descriptor is written from the stream dimensions
+    pub fn stream_frames(mut self) -> impl Stream<Item = Result<Vec<u8>, StreamError<E>>> {
+        let coalesce = self.coalesce;
+        let stream = try_stream! {
+            yield Chunk::Header(self.header_bytes(&[]));
+
+            let mut encoder = self.encoder();
+            let mut delays = self.delays();
+            let mut interval = self.ticker();
+            for _ in 0..self.frames.unwrap_or(usize::MAX) {
+                interval.tick().await;
+
+                let mut buf = Vec::new();
+
+                let frame = self.generate(&mut interval).await.map_err(StreamError::Generator)?;
+
+                encoder.try_write_frame(
+                    &mut buf,
+                    &frame,
+                    delays.next(),
+                    self.interlaced,
+                    self.dispose,
+                )?;
+
+                yield Chunk::Frame(buf);
+            }
+
+            if self.frames.is_some() {
+                yield Chunk::Trailer;
+            }
+        };
+
+        coalesce_frames(stream, coalesce)
+    }
+
+    // same as stream_frames, but with a global palette for frames to share
+    // frames that carry a palette of their own are written with it as their
+    // local color table, frames without one index into the global palette
+    // e.g. built with Frame::with_global_palette_rgba
+    pub fn stream_frames_with_palette(
+        mut self,
+        palette: GlobalPalette,
+    ) -> impl Stream<Item = Result<Vec<u8>, StreamError<E>>> {
+        let coalesce = self.coalesce;
+        let stream = try_stream! {
+            yield Chunk::Header(self.header_bytes(palette.palette()));
+
+            // frames indexing the global palette get its code size, like stream_with_palette
+            let mut local_encoder = self.encoder();
+            let mut global_encoder = self.palette_encoder(palette.palette().len() / 3);
+            let mut delays = self.delays();
+            let mut interval = self.ticker();
+            for _ in 0..self.frames.unwrap_or(usize::MAX) {
+                interval.tick().await;
+
+                let mut buf = Vec::new();
+
+                let frame = self.generate(&mut interval).await.map_err(StreamError::Generator)?;
+
+                let encoder = match frame.palette {
+                    Some(_) => &mut local_encoder,
+                    None => &mut global_encoder,
+                };
+                encoder.try_write_frame(
+                    &mut buf,
+                    &frame,
+                    delays.next(),
+                    self.interlaced,
+                    self.dispose,
+                )?;
+
+                yield Chunk::Frame(buf);
+            }
+
+            if self.frames.is_some() {
+                yield Chunk::Trailer;
+            }
+        };
+
+        coalesce_frames(stream, coalesce)
+    }
+}
+
+impl<S, F, E, R> GifStream<S, F>
+where
+    S: Clone + Send,
+    F: Fn(S) -> R,
+    R: Future<Output = Result<(Frame, DisposalMethod), E>> + Send + 'static,
+{
+    // same as stream_frames, but the generator also picks each frame's disposal method
+    // e.g. Keep for a background, then Previous for overlays that should be undone
+    pub fn stream_frames_disposed(mut self) -> impl Stream<Item = Result<Vec<u8>, StreamError<E>>> {
+        let coalesce = self.coalesce;
+        let stream = try_stream! {
+            yield Chunk::Header(self.header_bytes(&[]));
+
+            let mut encoder = self.encoder();
+            let mut delays = self.delays();
+            let mut interval = self.ticker();
+            for _ in 0..self.frames.unwrap_or(usize::MAX) {
+                interval.tick().await;
+
+                let mut buf = Vec::new();
+
+                let (frame, dispose) = self.generate(&mut interval).await.map_err(StreamError::Generator)?;
+
+                encoder.try_write_frame(
+                    &mut buf,
+                    &frame,
+                    delays.next(),
+                    self.interlaced,
+                    dispose,
+                )?;
+
+                yield Chunk::Frame(buf);
+            }
+
+            if self.frames.is_some() {
+                yield Chunk::Trailer;
+            }
+        };
+
+        coalesce_frames(stream, coalesce)
+    }
+}
+
+// wraps each encoded chunk of a gif stream in bytes::Bytes
+// every chunk is a freshly allocated Vec, so handing it to Bytes doesn't copy
+#[cfg(feature = "bytes")]
+pub fn bytes_stream<St, E>(stream: St) -> impl Stream<Item = Result<bytes::Bytes, E>>
+where
+    St: Stream<Item = Result<Vec<u8>, E>>,
+{
+    stream.map(|chunk| chunk.map(bytes::Bytes::from))
+}
+
+// the pieces the streams are made of, before coalesce_frames turns them into bytes
+enum Chunk {
+    Header(Vec<u8>),
+    Frame(Vec<u8>),
+    Trailer,
+}
+
+// groups the frames of a stream into chunks of n frames each (see coalesce)
+// the header stays a chunk of its own and the trailer goes out with the last chunk
+// an error flushes the frames gathered so far before it
+fn coalesce_frames<St, E>(stream: St, n: usize) -> impl Stream<Item = Result<Vec<u8>, E>>
+where
+    St: Stream<Item = Result<Chunk, E>>,
+{
+    async_stream::stream! {
+        let mut stream = std::pin::pin!(stream);
+        let mut batch = Vec::new();
+        let mut frames = 0;
+        while let Some(chunk) = stream.next().await {
+            match chunk {
+                Ok(Chunk::Header(header)) => {
+                    yield Ok(header);
+                }
+                Ok(Chunk::Frame(frame)) => {
+                    batch.extend_from_slice(&frame);
+                    frames += 1;
+                    if frames >= n {
+                        frames = 0;
+                        yield Ok(std::mem::take(&mut batch));
+                    }
+                }
+                Ok(Chunk::Trailer) => {
+                    GifEncoder::write_trailer(&mut batch);
+                    frames = 0;
+                    yield Ok(std::mem::take(&mut batch));
+                }
+                Err(e) => {
+                    if !batch.is_empty() {
+                        frames = 0;
+                        yield Ok(std::mem::take(&mut batch));
+                    }
+                    yield Err(e);
+                }
+            }
+        }
+
+        if !batch.is_empty() {
+            yield Ok(batch);
+        }
+    }
+}
+
+// calls on_frame with the index and the encoded bytes of every frame passing
+// through a gif stream, without consuming it, e.g. to record frame sizes
+// the first chunk (the header) and the trailer are not frames and are skipped
+// it counts chunks, so after coalesce(n) each call gets a chunk of up to n frames
+// and its index. the last chunk is passed on without the trailer it ends with
+pub fn inspect_frames<St, E, C>(
+    stream: St,
+    mut on_frame: C,
+) -> impl Stream<Item = Result<Vec<u8>, E>>
+where
+    St: Stream<Item = Result<Vec<u8>, E>>,
+    C: FnMut(usize, &[u8]),
+{
+    let mut chunks = 0;
+    stream.map(move |chunk| {
+        if let Ok(chunk) = &chunk {
+            let frames = &chunk[..frames_len(chunk)];
+            if chunks > 0 && !frames.is_empty() {
+                on_frame(chunks - 1, frames);
+            }
+            chunks += 1;
+        }
+
+        chunk
+    })
+}
+
+// the length of the frames at the start of chunk, up to the trailer if it has one
+// walks the blocks so bytes of image data are never taken for the trailer
+fn frames_len(chunk: &[u8]) -> usize {
+    let mut pos = 0;
+    while let Some(&block) = chunk.get(pos) {
+        pos = match block {
+            0x3B => return pos,
+            // extension, the label then sub-blocks
+            0x21 => sub_blocks_end(chunk, pos + 2),
+            // image descriptor, local color table, lzw code size then sub-blocks
+            0x2C => {
+                let flags = chunk.get(pos + 9).copied().unwrap_or(0);
+                let table = match flags & 0x80 {
+                    0 => 0,
+                    _ => 3 << ((flags & 0x07) + 1),
+                };
+                sub_blocks_end(chunk, pos + 10 + table + 1)
+            }
+            _ => return chunk.len(),
+        };
+    }
+
+    chunk.len()
+}
+
+// the position right after the block terminator of the sub-blocks starting at pos
+fn sub_blocks_end(chunk: &[u8], mut pos: usize) -> usize {
+    while let Some(&len) = chunk.get(pos) {
+        pos += 1 + len as usize;
+        if len == 0 {
+            return pos;
+        }
+    }
+
+    chunk.len()
+}
+
+// Reads any stream of encoded chunks as one continuous gif
+// reaches the end once the stream does, after the trailer for bounded streams
+// a stream error is returned as an io::Error
+pub struct GifReader<St> {
+    stream: Pin<Box<St>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl<St> GifReader<St> {
+    pub fn new(stream: St) -> Self {
+        Self {
+            stream: Box::pin(stream),
+            chunk: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl<St, E> AsyncRead for GifReader<St>
+where
+    St: Stream<Item = Result<Vec<u8>, E>>,
+    E: Into<Box<dyn Error + Send + Sync>>,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        while this.pos == this.chunk.len() {
+            match ready!(this.stream.as_mut().poll_next(cx)) {
+                Some(Ok(chunk)) => {
+                    this.chunk = chunk;
+                    this.pos = 0;
+                }
+                Some(Err(e)) => return Poll::Ready(Err(io::Error::other(e))),
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+
+        let n = buf.remaining().min(this.chunk.len() - this.pos);
+        buf.put_slice(&this.chunk[this.pos..this.pos + n]);
+        this.pos += n;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+// Draws frame onto an rgba canvas `width` pixels wide the way a decoder would,
+// with the colors of its palette, transparent pixels leave the canvas alone
+fn draw_frame(canvas: &mut [u8], width: u16, frame: &Frame) {
+    let palette = frame.palette.as_deref().unwrap_or_default();
+    let (left, top) = (frame.left as usize, frame.top as usize);
+    let row_len = frame.width as usize;
+    if row_len == 0 {
+        return;
+    }
+
+    for (y, row) in frame.buffer.chunks(row_len).enumerate() {
+        let start = ((top + y) * width as usize + left) * 4;
+        let Some(out) = canvas.get_mut(start..start + row.len() * 4) else {
+            break;
+        };
+
+        for (pix, &index) in out.chunks_exact_mut(4).zip(row) {
+            let color = palette.get(index as usize * 3..index as usize * 3 + 3);
+            match color {
+                Some(rgb) if frame.transparent != Some(index) => {
+                    pix[..3].copy_from_slice(rgb);
+                    pix[3] = 0xFF;
+                }
+                _ => {}
+            }
+        }
+    }
+}
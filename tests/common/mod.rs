@@ -0,0 +1,212 @@
+#![allow(dead_code)]
+
+use weezl::{decode::Decoder, BitOrder};
+
+// a small gif decoder for checking what the encoder wrote, it keeps every block
+// it understands and composites the frames so disposal can be checked too
+
+#[derive(Debug, Clone)]
+pub struct DecodedFrame {
+    pub left: u16,
+    pub top: u16,
+    pub width: u16,
+    pub height: u16,
+    pub interlaced: bool,
+    pub local: bool,
+    pub palette: Vec<u8>,
+    pub min_code_size: u8,
+    // in display order, interlaced rows are put back where they belong
+    pub indices: Vec<u8>,
+    pub transparent: Option<u8>,
+    pub dispose: u8,
+    pub delay: u16,
+    // the whole screen after this frame was drawn
+    pub canvas: Vec<[u8; 4]>,
+}
+
+#[derive(Debug, Default)]
+pub struct DecodedGif {
+    pub width: u16,
+    pub height: u16,
+    pub background: u8,
+    pub global_palette: Option<Vec<u8>>,
+    pub frames: Vec<DecodedFrame>,
+    pub trailer: bool,
+}
+
+fn u16le(d: &[u8], i: usize) -> u16 {
+    u16::from_le_bytes([d[i], d[i + 1]])
+}
+
+fn sub_blocks(d: &[u8], i: &mut usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    loop {
+        let n = d[*i] as usize;
+        *i += 1;
+        if n == 0 {
+            return out;
+        }
+        out.extend_from_slice(&d[*i..*i + n]);
+        *i += n;
+    }
+}
+
+// the indices of an interlaced image, passes of every 8th row from 0, every 8th
+// from 4, every 4th from 2 and every 2nd from 1, put back in display order
+fn deinterlace(raw: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut indices = vec![0; raw.len()];
+    let mut rows = raw.chunks_exact(width);
+    for (start, step) in [(0, 8), (4, 8), (2, 4), (1, 2)] {
+        for row in (start..height).step_by(step) {
+            let src = rows.next().unwrap();
+            indices[row * width..(row + 1) * width].copy_from_slice(src);
+        }
+    }
+    indices
+}
+
+pub fn decode(d: &[u8]) -> DecodedGif {
+    assert!(d.starts_with(b"GIF87a") || d.starts_with(b"GIF89a"));
+
+    let mut gif = DecodedGif {
+        width: u16le(d, 6),
+        height: u16le(d, 8),
+        background: d[11],
+        ..Default::default()
+    };
+
+    let flags = d[10];
+    let mut i = 13;
+    if flags & 0x80 != 0 {
+        let n = 3 * (2 << (flags & 7));
+        gif.global_palette = Some(d[i..i + n].to_vec());
+        i += n;
+    }
+
+    let screen = gif.width as usize;
+    let mut canvas = vec![[0u8; 4]; screen * gif.height as usize];
+    // packed flags, delay and transparent index of the pending graphic control
+    let mut control: Option<(u8, u16, u8)> = None;
+    // disposal of the last frame, applied before the next one draws
+    let mut pending: Option<(u8, DecodedFrame, Vec<[u8; 4]>)> = None;
+
+    while i < d.len() {
+        match d[i] {
+            0x21 => {
+                let label = d[i + 1];
+                i += 2;
+                if label == 0xF9 {
+                    assert_eq!(d[i], 4);
+                    control = Some((d[i + 1], u16le(d, i + 2), d[i + 4]));
+                    i += 5;
+                    assert_eq!(d[i], 0);
+                    i += 1;
+                } else {
+                    if label == 0xFF {
+                        i += 1 + d[i] as usize;
+                    }
+                    sub_blocks(d, &mut i);
+                }
+            }
+            0x2C => {
+                let left = u16le(d, i + 1);
+                let top = u16le(d, i + 3);
+                let width = u16le(d, i + 5);
+                let height = u16le(d, i + 7);
+                let flags = d[i + 9];
+                i += 10;
+
+                let local = flags & 0x80 != 0;
+                let palette = if local {
+                    let n = 3 * (2 << (flags & 7));
+                    i += n;
+                    d[i - n..i].to_vec()
+                } else {
+                    gif.global_palette.clone().expect("no color table")
+                };
+
+                let min_code_size = d[i];
+                i += 1;
+                let data = sub_blocks(d, &mut i);
+                let mut raw = Vec::new();
+                let result = Decoder::new(BitOrder::Lsb, min_code_size)
+                    .into_vec(&mut raw)
+                    .decode_all(&data);
+                assert!(result.status.is_ok(), "lzw data doesn't decode");
+                assert_eq!(raw.len(), width as usize * height as usize);
+
+                let interlaced = flags & 0x40 != 0;
+                let indices = if interlaced {
+                    deinterlace(&raw, width as usize, height as usize)
+                } else {
+                    raw
+                };
+
+                if let Some((dispose, last, saved)) = pending.take() {
+                    match dispose {
+                        2 => {
+                            for y in last.top..last.top + last.height {
+                                let row = y as usize * screen;
+                                let start = row + last.left as usize;
+                                canvas[start..start + last.width as usize].fill([0; 4]);
+                            }
+                        }
+                        3 => canvas = saved,
+                        _ => {}
+                    }
+                }
+
+                let (packed, delay, index) = control.take().unwrap_or_default();
+                let transparent = (packed & 1 != 0).then_some(index);
+                let saved = canvas.clone();
+                for (n, &idx) in indices.iter().enumerate() {
+                    if Some(idx) == transparent {
+                        continue;
+                    }
+                    let x = left as usize + n % width as usize;
+                    let y = top as usize + n / width as usize;
+                    let p = idx as usize * 3;
+                    canvas[y * screen + x] = [palette[p], palette[p + 1], palette[p + 2], 255];
+                }
+
+                let frame = DecodedFrame {
+                    left,
+                    top,
+                    width,
+                    height,
+                    interlaced,
+                    local,
+                    palette,
+                    min_code_size,
+                    indices,
+                    transparent,
+                    dispose: (packed >> 2) & 7,
+                    delay,
+                    canvas: canvas.clone(),
+                };
+                pending = Some((frame.dispose, frame.clone(), saved));
+                gif.frames.push(frame);
+            }
+            0x3B => {
+                gif.trailer = true;
+                i += 1;
+                assert_eq!(i, d.len(), "data after the trailer");
+            }
+            b => panic!("unexpected block {b:#x} at {i}"),
+        }
+    }
+
+    gif
+}
+
+// a w x h opaque rgba gradient, t shifts the red channel between frames
+pub fn gradient(width: u16, height: u16, t: u8) -> Vec<u8> {
+    let mut data = Vec::with_capacity(width as usize * height as usize * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let r = (x as u8).wrapping_mul(16).wrapping_add(t);
+            data.extend_from_slice(&[r, (y as u8).wrapping_mul(20), 7, 255]);
+        }
+    }
+    data
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use gifstream::{DisposalMethod, Frame, GifBuilder, GifEncoder};
+
+// a red frame then a blue one with its top half transparent, both disposed to
+// the background, the holes have to show the cleared canvas and not the red
+#[test]
+fn background_clears_under_transparent_pixels() {
+    let red = [255, 0, 0, 255].repeat(16);
+    let mut holed = [0, 0, 255, 255].repeat(16);
+    for pix in holed.chunks_exact_mut(4).take(8) {
+        pix[3] = 0;
+    }
+
+    let first = Frame::from_rgba_exact(4, 4, &red, 10);
+    let second = Frame::from_rgba_exact(4, 4, &holed, 10);
+    assert!(second.transparent.is_some());
+
+    let mut gif = GifBuilder::new(4, 4);
+    gif.push_frame(&first, 10, DisposalMethod::Background)
+        .unwrap();
+    gif.push_frame(&second, 10, DisposalMethod::Background)
+        .unwrap();
+    let decoded = common::decode(&gif.finish());
+
+    let [a, b] = &decoded.frames[..] else {
+        panic!("expected two frames");
+    };
+    assert_eq!((a.dispose, a.transparent), (2, None));
+    assert_eq!((b.dispose, b.transparent), (2, second.transparent));
+
+    assert!(a.canvas.iter().all(|&pix| pix == [255, 0, 0, 255]));
+    assert!(b.canvas[..8].iter().all(|&pix| pix == [0, 0, 0, 0]));
+    assert!(b.canvas[8..].iter().all(|&pix| pix == [0, 0, 255, 255]));
+}
+
+// a red background frame kept on screen, then three single pixel frames that
+// are each disposed back to it, so every one of them is drawn on plain red
+#[test]
+fn previous_restores_the_canvas_before_the_frame() {
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    let colors = [[0, 0, 255], [0, 255, 0], [255, 255, 255]];
+
+    let mut gif = GifBuilder::new(4, 1);
+    let background = Frame::from_palatte_rgba(4, 1, &[0; 4], &[255, 0, 0, 0, 0, 0]);
+    gif.push_frame(&background, 10, DisposalMethod::Keep)
+        .unwrap();
+
+    for (left, color) in (1..).zip(colors) {
+        let mut frame =
+            Frame::from_palatte_rgba(1, 1, &[0], &[color[0], color[1], color[2], 0, 0, 0]);
+        frame.left = left;
+        gif.push_frame(&frame, 10, DisposalMethod::Previous)
+            .unwrap();
+    }
+
+    let decoded = common::decode(&gif.finish());
+    assert_eq!(decoded.frames.len(), 4);
+    assert_eq!(decoded.frames[0].canvas, [RED; 4]);
+
+    for (i, color) in colors.into_iter().enumerate() {
+        let mut expected = [RED; 4];
+        expected[i + 1] = [color[0], color[1], color[2], 255];
+
+        let frame = &decoded.frames[i + 1];
+        assert_eq!(frame.dispose, 3);
+        assert_eq!(frame.canvas, expected);
+    }
+}
+
+// the graphic control extension packs the disposal method into bits 2-4 and the
+// transparent color flag into bit 0
+#[test]
+fn control_extension_packed_byte() {
+    let methods = [
+        (DisposalMethod::Any, 0b0000_0000),
+        (DisposalMethod::Keep, 0b0000_0100),
+        (DisposalMethod::Background, 0b0000_1000),
+        (DisposalMethod::Previous, 0b0000_1100),
+    ];
+
+    for (dispose, bits) in methods {
+        for transparent in [None, Some(1)] {
+            let mut frame = Frame::from_indexed_rgba(2, 1, &[0, 1]);
+            frame.transparent = transparent;
+
+            let mut buf = Vec::new();
+            GifEncoder::write_frame_header(&mut buf, &frame, 10, false, dispose);
+
+            let packed = bits | transparent.is_some() as u8;
+            let index = transparent.unwrap_or(0);
+            assert_eq!(
+                buf[..8],
+                [0x21, 0xF9, 4, packed, 10, 0, index, 0],
+                "{dispose:?} {transparent:?}"
+            );
+        }
+    }
+}
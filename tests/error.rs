@@ -0,0 +1,38 @@
+use std::time::Duration;
+
+use gifstream::{lzw_decode, GifError};
+
+#[test]
+fn errors_compare_by_variant_and_value() {
+    assert_eq!(GifError::InvalidSpeed(0), GifError::InvalidSpeed(0));
+    assert_ne!(GifError::InvalidSpeed(0), GifError::InvalidSpeed(31));
+    assert_ne!(GifError::InvalidColorCount(0), GifError::PaletteTooLarge(0));
+    assert_eq!(
+        GifError::BufferSizeMismatch {
+            expected: 4,
+            got: 3
+        },
+        GifError::BufferSizeMismatch {
+            expected: 4,
+            got: 3
+        }
+    );
+    assert_ne!(
+        GifError::InvalidDelay(Duration::from_millis(5)),
+        GifError::InvalidDelay(Duration::from_millis(9))
+    );
+    assert_eq!(GifError::EmptyFrame, GifError::EmptyFrame.clone());
+}
+
+// two decodes failing the same way give equal lzw errors
+#[test]
+fn lzw_errors_compare() {
+    // a code far past the end of the dictionary right after the clear code
+    let broken = [2, 0x0C, 0xFF, 0xFF, 0xFF];
+    let a = lzw_decode(&broken).unwrap_err();
+    let b = lzw_decode(&broken).unwrap_err();
+
+    assert!(matches!(a, GifError::Lzw(_)), "{a:?}");
+    assert_eq!(a, b);
+    assert_ne!(a, GifError::InvalidCodeSize(2));
+}
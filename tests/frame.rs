@@ -0,0 +1,145 @@
+mod common;
+
+use gifstream::{DisposalMethod, Frame, GifBuilder, GifEncoder, GifError, GlobalPalette};
+
+#[test]
+fn try_from_rgba_rejects_impossible_geometry() {
+    let data = common::gradient(4, 3, 0);
+
+    assert_eq!(
+        Frame::try_from_rgba(0, 3, &data, 10).err(),
+        Some(GifError::EmptyFrame)
+    );
+    assert_eq!(
+        Frame::try_from_rgba(4, 0, &data, 10).err(),
+        Some(GifError::EmptyFrame)
+    );
+    assert_eq!(
+        Frame::try_from_rgba(4, 3, &data[..8], 10).err(),
+        Some(GifError::BufferSizeMismatch {
+            expected: 48,
+            got: 8
+        })
+    );
+
+    let frame = Frame::try_from_rgba(4, 3, &data, 10).unwrap();
+    assert_eq!((frame.width, frame.height), (4, 3));
+    assert_eq!(frame.buffer.len(), 12);
+}
+
+#[test]
+fn try_write_frame_header_rejects_impossible_geometry() {
+    let mut frame = Frame::from_indexed_rgba(2, 1, &[0, 1]);
+    let mut buf = Vec::new();
+
+    frame.width = 3;
+    let written =
+        GifEncoder::try_write_frame_header(&mut buf, &frame, 10, false, DisposalMethod::Keep);
+    assert!(written.is_err());
+    assert!(buf.is_empty());
+
+    frame.width = 0;
+    let written =
+        GifEncoder::try_write_frame_header(&mut buf, &frame, 10, false, DisposalMethod::Keep);
+    assert_eq!(written.err(), Some(GifError::EmptyFrame));
+    assert!(buf.is_empty());
+}
+
+// a local palette over 256 colors is cut to 256 by the infallible writers, so the
+// frame still decodes, and only the try_ writers reject it
+#[test]
+fn oversized_palette_is_clamped() {
+    let mut frame = Frame::from_indexed_rgba(2, 1, &[0, 255]);
+    frame.palette = Some((0..300 * 3).map(|c| c as u8).collect());
+
+    let mut gif = Vec::new();
+    GifEncoder::write_screen_desc(&mut gif, 2, 1, None);
+    GifEncoder::write_frame(&mut gif, &frame, 10, false, DisposalMethod::Keep);
+    GifEncoder::write_trailer(&mut gif);
+
+    let decoded = common::decode(&gif);
+    assert_eq!(decoded.frames[0].palette.len(), 768);
+    assert_eq!(decoded.frames[0].indices, [0, 255]);
+
+    let written =
+        GifEncoder::try_write_frame(&mut Vec::new(), &frame, 10, false, DisposalMethod::Keep);
+    assert_eq!(written.err(), Some(GifError::PaletteTooLarge(300)));
+}
+
+const ONE: [[u8; 4]; 1] = [[10, 200, 30, 255]];
+const FOUR: [[u8; 4]; 4] = [
+    [255, 0, 0, 255],
+    [0, 255, 0, 255],
+    [0, 0, 255, 255],
+    [0, 0, 0, 0],
+];
+
+// the shown color is within a few steps of the source on every channel
+fn close(shown: &[u8; 4], pix: &[u8; 4]) -> bool {
+    shown[..3].iter().zip(pix).all(|(s, p)| s.abs_diff(*p) <= 4)
+}
+
+// the palette of a favicon sized frame is learned from a handful of pixels at
+// any speed, and the frame has to decode to its own colors
+#[test]
+fn tiny_local_palette_frames() {
+    for (w, h, pixels) in [(1, 1, &ONE[..]), (2, 2, &FOUR[..])] {
+        let data = pixels.concat();
+        for speed in [1, 10, 30] {
+            let frame = Frame::from_rgba(w, h, &data, speed);
+            assert_eq!(frame.buffer.len(), pixels.len());
+
+            let mut gif = GifBuilder::new(w, h);
+            gif.push_frame(&frame, 10, DisposalMethod::Keep).unwrap();
+            let decoded = common::decode(&gif.finish());
+
+            let canvas = &decoded.frames[0].canvas;
+            for (shown, pix) in canvas.iter().zip(pixels) {
+                assert_eq!(shown[3], pix[3]);
+                if pix[3] != 0 {
+                    assert!(close(shown, pix), "speed {speed}: {shown:?} for {pix:?}");
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn tiny_global_palette_frames() {
+    let opaque = &FOUR[..3];
+    for (w, h, pixels) in [(1, 1, &ONE[..]), (3, 1, opaque), (2, 2, &FOUR[..])] {
+        let data = pixels.concat();
+        for speed in [1, 10, 30] {
+            let gp = GlobalPalette::new(speed, 256, &data);
+            let frame = Frame::with_global_palette_rgba(w, h, &data, &gp);
+            assert_eq!(frame.buffer.len(), pixels.len());
+
+            let mut gif = GifBuilder::new(w, h).global_palette(gp.palette());
+            gif.push_frame(&frame, 10, DisposalMethod::Keep).unwrap();
+            let decoded = common::decode(&gif.finish());
+            assert!(!decoded.frames[0].local);
+
+            for (shown, pix) in decoded.frames[0].canvas.iter().zip(pixels) {
+                if pix[3] != 0 {
+                    assert!(close(shown, pix), "speed {speed}: {shown:?} for {pix:?}");
+                }
+            }
+        }
+    }
+}
+
+// a frame with few colors keeps exactly those, in a table sized to them
+#[test]
+fn exact_palette_is_sized_to_the_colors() {
+    let data = common::gradient(5, 8, 0);
+    let frame = Frame::from_rgba_exact(5, 8, &data, 10);
+    assert_eq!(frame.palette.as_ref().unwrap().len(), 40 * 3);
+
+    let mut gif = GifBuilder::new(5, 8);
+    gif.push_frame(&frame, 10, DisposalMethod::Keep).unwrap();
+    let decoded = common::decode(&gif.finish());
+    assert_eq!(decoded.frames[0].palette.len(), 64 * 3);
+
+    let shown = decoded.frames[0].canvas.concat();
+    assert_eq!(shown, data);
+}
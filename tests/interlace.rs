@@ -0,0 +1,26 @@
+mod common;
+
+use gifstream::{DisposalMethod, Frame, GifBuilder};
+
+fn encode(frame: &Frame, interlaced: bool) -> Vec<u8> {
+    let mut gif = GifBuilder::new(frame.width, frame.height).interlaced(interlaced);
+    gif.push_frame(frame, 10, DisposalMethod::Keep).unwrap();
+    gif.finish()
+}
+
+// heights around the pass boundaries, including ones where later passes are empty
+#[test]
+fn interlaced_decodes_like_progressive() {
+    for height in [1, 2, 3, 4, 5, 7, 8, 9, 16, 17, 31] {
+        let data = common::gradient(3, height, 0);
+        let frame = Frame::from_rgba(3, height, &data, 10);
+
+        let progressive = common::decode(&encode(&frame, false));
+        let interlaced = common::decode(&encode(&frame, true));
+
+        assert!(!progressive.frames[0].interlaced);
+        assert!(interlaced.frames[0].interlaced);
+        assert_eq!(interlaced.frames[0].indices, frame.buffer);
+        assert_eq!(progressive.frames[0].canvas, interlaced.frames[0].canvas);
+    }
+}
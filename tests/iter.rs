@@ -0,0 +1,69 @@
+mod common;
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use gifstream::{GifError, GifStream, Repeat};
+
+// state that isn't Send works without a runtime
+#[test]
+fn iter_with_rc_state() {
+    let counter = Rc::new(Cell::new(0u8));
+    let gs = GifStream::new(Duration::from_millis(100), 4, 3, counter.clone(), |c| {
+        c.set(c.get() + 1);
+        Some(common::gradient(4, 3, c.get() * 40))
+    });
+
+    let gif: Vec<u8> = gs
+        .take_frames(3)
+        .iter()
+        .collect::<Result<Vec<_>, GifError>>()
+        .unwrap()
+        .concat();
+
+    let decoded = common::decode(&gif);
+    assert_eq!(decoded.frames.len(), 3);
+    assert!(decoded.trailer);
+    assert_eq!(counter.get(), 3);
+}
+
+// header_bytes is exactly what a stream starts with, extensions included
+#[test]
+fn header_bytes_is_the_first_chunk() {
+    let gs = GifStream::new(Duration::from_millis(100), 4, 3, (), |_| {
+        Some(common::gradient(4, 3, 0))
+    })
+    .repeat(Repeat::Infinite)
+    .application_extension(*b"GIFSTREA", *b"M01", b"hello")
+    .take_frames(1);
+
+    let header = gs.header_bytes(&[]);
+    let first = gs.iter().next().unwrap().unwrap();
+    assert_eq!(header, first);
+    assert!(header.windows(11).any(|w| w == b"NETSCAPE2.0"));
+    assert!(header.windows(11).any(|w| w == b"GIFSTREAM01"));
+}
+
+// there's no cap on how much extension data a stream carries
+#[test]
+fn large_application_extensions() {
+    let mut gs = GifStream::new(Duration::from_millis(100), 4, 3, (), |_| {
+        Some(common::gradient(4, 3, 0))
+    })
+    .take_frames(1);
+    for _ in 0..4 {
+        gs = gs.application_extension(*b"GIFSTREA", *b"M01", &[7; 4000]);
+    }
+
+    let gif: Vec<u8> = gs
+        .iter()
+        .collect::<Result<Vec<_>, GifError>>()
+        .unwrap()
+        .concat();
+    assert!(gif.len() > 4 * 4000);
+
+    let decoded = common::decode(&gif);
+    assert_eq!(decoded.frames.len(), 1);
+    assert!(decoded.trailer);
+}
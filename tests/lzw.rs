@@ -0,0 +1,43 @@
+use gifstream::{lzw_decode, lzw_encode, GifError};
+
+fn round_trip(data: &[u8]) {
+    let mut encoded = Vec::new();
+    lzw_encode(&mut encoded, data);
+    assert_eq!(lzw_decode(&encoded).unwrap(), data);
+
+    // encoding after existing output leaves it alone
+    let mut appended = vec![1, 2, 3];
+    lzw_encode(&mut appended, data);
+    assert_eq!(appended[..3], [1, 2, 3]);
+    assert_eq!(appended[3..], encoded[..]);
+}
+
+#[test]
+fn empty() {
+    round_trip(&[]);
+}
+
+#[test]
+fn all_the_same() {
+    round_trip(&[0; 1]);
+    round_trip(&[0; 10_000]);
+    round_trip(&[255; 10_000]);
+}
+
+// every index from 0 to 255, in order and then in a pattern that keeps
+// filling the dictionary so the encoder has to reset it
+#[test]
+fn full_index_range() {
+    let ramp: Vec<u8> = (0..=255).collect();
+    round_trip(&ramp);
+
+    let spread: Vec<u8> = (0..20_000).map(|i| (i * 7 % 256) as u8).collect();
+    round_trip(&spread);
+}
+
+#[test]
+fn decode_rejects_bad_code_sizes() {
+    assert_eq!(lzw_decode(&[]), Err(GifError::InvalidCodeSize(0)));
+    assert_eq!(lzw_decode(&[1, 0]), Err(GifError::InvalidCodeSize(1)));
+    assert_eq!(lzw_decode(&[9, 0]), Err(GifError::InvalidCodeSize(9)));
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use gifstream::{Frame, GifError, GlobalPalette, MedianCut, PaletteOptions, Quantizer};
+
+// every other pixel is transparent, a stride over all the pixels would only land
+// on the holes, the opaque ones are what gets sampled
+#[test]
+fn median_cut_samples_opaque_pixels() {
+    let colors = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 255]];
+    let data: Vec<u8> = colors
+        .iter()
+        .flat_map(|c| [0, 0, 0, 0, c[0], c[1], c[2], 255])
+        .collect();
+
+    for speed in [1, 10, 30] {
+        let mc = MedianCut::new(speed, 256, &data);
+        for c in colors {
+            let i = mc.index_of(&[c[0], c[1], c[2], 255]) as usize * 3;
+            assert_eq!(mc.palette()[i..i + 3], c, "speed {speed}");
+        }
+    }
+}
+
+// the sample factor is set on its own, and learns the same palettes as the
+// matching speed did
+#[test]
+fn palette_options_sample_factor() {
+    let data = common::gradient(32, 32, 0);
+
+    for factor in [1, 10, 30] {
+        let options = PaletteOptions::new().sample_factor(factor);
+        let gp = GlobalPalette::with_options(&data, options);
+        assert_eq!(
+            gp.palette(),
+            GlobalPalette::new(factor, 256, &data).palette()
+        );
+
+        let frame = Frame::from_rgba_with_options(32, 32, &data, options);
+        assert_eq!(
+            frame.palette,
+            Frame::from_rgba(32, 32, &data, factor).palette
+        );
+    }
+
+    let options = PaletteOptions::new()
+        .quantizer(Quantizer::MedianCut)
+        .colors(16);
+    let gp = GlobalPalette::with_options(&data, options);
+    assert!(gp.palette().len() <= 16 * 3);
+
+    assert_eq!(
+        PaletteOptions::new().try_sample_factor(0).err(),
+        Some(GifError::InvalidSpeed(0))
+    );
+    assert_eq!(
+        PaletteOptions::new().try_colors(257).err(),
+        Some(GifError::InvalidColorCount(257))
+    );
+}
@@ -0,0 +1,66 @@
+use gifstream::scale::{resize_rgba, try_resize_rgba};
+use gifstream::{GifError, ResizeFilter};
+
+const WHITE: [u8; 4] = [255, 255, 255, 255];
+const CLEAR: [u8; 4] = [0, 0, 0, 0];
+
+#[test]
+fn nearest_repeats_and_drops_pixels() {
+    let src = [WHITE, CLEAR].concat();
+
+    let up = resize_rgba(&src, 2, 1, 4, 1, ResizeFilter::Nearest);
+    assert_eq!(up, [WHITE, WHITE, CLEAR, CLEAR].concat());
+
+    let down = resize_rgba(&up, 4, 1, 2, 1, ResizeFilter::Nearest);
+    assert_eq!(down, src);
+}
+
+#[test]
+fn bilinear_blends_opaque_neighbours() {
+    let src = [[0, 0, 0, 255], WHITE].concat();
+    let out = resize_rgba(&src, 2, 1, 1, 1, ResizeFilter::Bilinear);
+    assert!(
+        out[..3].iter().all(|&c| (120..=135).contains(&c)),
+        "{out:?}"
+    );
+    assert_eq!(out[3], 255);
+}
+
+// transparent black next to white only lowers the alpha, the color stays white
+#[test]
+fn bilinear_doesnt_darken_next_to_transparent_pixels() {
+    let src = [WHITE, CLEAR].concat();
+
+    let down = resize_rgba(&src, 2, 1, 1, 1, ResizeFilter::Bilinear);
+    assert_eq!(down[..3], [255, 255, 255]);
+    assert!((100..160).contains(&down[3]), "{down:?}");
+
+    let up = resize_rgba(&src, 2, 1, 4, 2, ResizeFilter::Bilinear);
+    for pix in up.chunks_exact(4) {
+        assert!(pix[3] == 0 || pix[..3] == [255, 255, 255], "{pix:?}");
+    }
+}
+
+#[test]
+fn fully_transparent_stays_transparent_black() {
+    let src = [[9, 80, 200, 0], CLEAR, [255, 0, 0, 0], CLEAR].concat();
+    let out = resize_rgba(&src, 2, 2, 3, 3, ResizeFilter::Bilinear);
+    assert_eq!(out, CLEAR.repeat(9));
+}
+
+#[test]
+fn try_resize_rejects_bad_sources() {
+    let src = [WHITE, CLEAR].concat();
+    assert_eq!(
+        try_resize_rgba(&src, 3, 1, 1, 1, ResizeFilter::Nearest).err(),
+        Some(GifError::BufferSizeMismatch {
+            expected: 12,
+            got: 8
+        })
+    );
+    assert_eq!(
+        try_resize_rgba(&[], 0, 1, 1, 1, ResizeFilter::Bilinear).err(),
+        Some(GifError::EmptyFrame)
+    );
+    assert!(resize_rgba(&src, 2, 1, 0, 0, ResizeFilter::Bilinear).is_empty());
+}
@@ -0,0 +1,138 @@
+#![cfg(feature = "tokio")]
+
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::StreamExt;
+use gifstream::{inspect_frames, Frame, GifStream, GlobalPalette, PaletteOptions, Quantizer};
+
+// a black 4x4 frame with a red pixel walking across it
+fn walking_pixel(n: usize) -> Vec<u8> {
+    let mut data = [0, 0, 0, 255].repeat(16);
+    let i = n % 16 * 4;
+    data[i..i + 4].copy_from_slice(&[255, 0, 0, 255]);
+    data
+}
+
+// diffs are taken against what the viewer decoded, so what it ends up showing
+// is every source frame, keyframes or not
+#[tokio::test]
+async fn keyframed_shows_every_frame() {
+    for quantizer in [Quantizer::NeuQuant, Quantizer::MedianCut] {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let gs = GifStream::new(Duration::from_millis(10), 4, 4, counter, |n| async move {
+            Ok::<_, ()>(walking_pixel(n.fetch_add(1, Ordering::SeqCst)))
+        })
+        .quantizer(quantizer)
+        .take_frames(7);
+
+        let chunks: Vec<_> = gs.stream_keyframed(3).collect().await;
+        let gif: Vec<u8> = chunks
+            .into_iter()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>()
+            .concat();
+
+        let decoded = common::decode(&gif);
+        assert_eq!(decoded.frames.len(), 7);
+        assert!(decoded.trailer);
+        for (n, frame) in decoded.frames.iter().enumerate() {
+            assert_eq!(
+                frame.canvas.concat(),
+                walking_pixel(n),
+                "{quantizer:?} frame {n}"
+            );
+        }
+    }
+}
+
+// frames indexing the global palette are written with its code size, frames
+// with their own palette with the smallest one their table fits
+#[tokio::test]
+async fn frames_with_palette_code_sizes() {
+    let gp =
+        GlobalPalette::with_options(&common::gradient(8, 8, 0), PaletteOptions::new().colors(16));
+    assert_eq!(gp.palette().len(), 16 * 3);
+
+    let counter = Arc::new(AtomicUsize::new(0));
+    let gs = GifStream::new(Duration::from_millis(10), 8, 1, counter, |n| async move {
+        let frame = match n.fetch_add(1, Ordering::SeqCst) {
+            0 => Frame::from_indexed_rgba(8, 1, &[1; 8]),
+            _ => Frame::from_palatte_rgba(8, 1, &[1; 8], &[0, 0, 0, 255, 0, 0]),
+        };
+        Ok::<_, ()>(frame)
+    })
+    .take_frames(2);
+
+    let chunks: Vec<_> = gs.stream_frames_with_palette(gp).collect().await;
+    let gif = chunks
+        .into_iter()
+        .map(Result::unwrap)
+        .collect::<Vec<_>>()
+        .concat();
+
+    let decoded = common::decode(&gif);
+    let [global, local] = &decoded.frames[..] else {
+        panic!("expected two frames");
+    };
+    assert!(!global.local);
+    assert_eq!(global.min_code_size, 4);
+    assert!(local.local);
+    assert_eq!(local.min_code_size, 2);
+}
+
+// five frames two at a time, the last chunk has the odd frame and the trailer,
+// inspect_frames sees the three frame chunks without it
+#[tokio::test]
+async fn coalesce_flushes_the_trailer() {
+    let counter = Arc::new(AtomicUsize::new(0));
+    let gs = GifStream::new(Duration::from_millis(10), 4, 4, counter, |n| async move {
+        Ok::<_, ()>(walking_pixel(n.fetch_add(1, Ordering::SeqCst)))
+    })
+    .take_frames(5)
+    .coalesce(2);
+
+    let mut sizes = Vec::new();
+    let stream = inspect_frames(gs.stream(), |i, frames| sizes.push((i, frames.len())));
+    let chunks: Vec<Vec<u8>> = stream.map(Result::unwrap).collect().await;
+
+    assert_eq!(chunks.len(), 4);
+    assert_eq!(chunks[3].last(), Some(&0x3B));
+    let expected = [
+        (0, chunks[1].len()),
+        (1, chunks[2].len()),
+        (2, chunks[3].len() - 1),
+    ];
+    assert_eq!(sizes, expected);
+
+    let decoded = common::decode(&chunks.concat());
+    assert_eq!(decoded.frames.len(), 5);
+    assert!(decoded.trailer);
+}
+
+// dedupe re-yields the cached frame, coalesced like any other, and the file
+// still decodes to every frame with the trailer at the end
+#[tokio::test]
+async fn dedupe_with_coalesce() {
+    let counter = Arc::new(AtomicUsize::new(0));
+    let gs = GifStream::new(Duration::from_millis(10), 4, 4, counter, |n| async move {
+        // the same frame three times, then it moves
+        Ok::<_, ()>(walking_pixel(n.fetch_add(1, Ordering::SeqCst) / 3))
+    })
+    .take_frames(5)
+    .dedupe_identical(true)
+    .coalesce(2);
+
+    let chunks: Vec<Vec<u8>> = gs.stream().map(Result::unwrap).collect().await;
+    assert_eq!(chunks.len(), 4);
+
+    let decoded = common::decode(&chunks.concat());
+    assert_eq!(decoded.frames.len(), 5);
+    assert!(decoded.trailer);
+    for (n, frame) in decoded.frames.iter().enumerate() {
+        assert_eq!(frame.canvas.concat(), walking_pixel(n / 3), "frame {n}");
+    }
+}